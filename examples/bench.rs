@@ -0,0 +1,46 @@
+//! Measures how many ticks per second each compute kernel manages.
+//!
+//! `cargo run --release --example bench -- [WIDTHxHEIGHT] [TICKS]`, defaults to 3840x2160 and 600 ticks.
+
+use std::time::Instant;
+
+use ow_my_lib::{Kernel, Rule, Simulation};
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (width, height) = match args.next() {
+        Some(size) => {
+            let (width, height) = size
+                .split_once('x')
+                .ok_or_else(|| anyhow::anyhow!("Size should look like 1920x1080, got {size}"))?;
+            (width.parse()?, height.parse()?)
+        }
+        None => (3840, 2160),
+    };
+    let ticks: u32 = match args.next() {
+        Some(ticks) => ticks.parse()?,
+        None => 600,
+    };
+    smol::block_on(async {
+        let mut sim = Simulation::new(width, height, Rule::default()).await?;
+        anyhow::ensure!(
+            sim.is_gpu(),
+            "No adapter found, there's nothing to benchmark"
+        );
+        println!("{width}x{height}, {ticks} ticks");
+        for kernel in [Kernel::Naive, Kernel::Tiled] {
+            sim.set_kernel(kernel);
+            // warm up, the first submissions include pipeline setup on some drivers
+            sim.advance(10);
+            let start = Instant::now();
+            sim.advance(ticks);
+            let elapsed = start.elapsed().as_secs_f64();
+            println!(
+                "{kernel}: {:.1} ticks/s ({:.2} ms/tick)",
+                ticks as f64 / elapsed,
+                elapsed * 1000.0 / ticks as f64
+            );
+        }
+        Ok(())
+    })
+}
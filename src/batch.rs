@@ -0,0 +1,360 @@
+// running without a window until the grid settles down, for sweeping over lots of rules
+
+use anyhow::Context;
+// not std's Instant, that doesn't work on the web
+use game_loop::{Time, TimeTrait};
+use tracing::info;
+
+use crate::{config::Config, metrics::MetricsWriter, Rule, Simulation, Snapshot, Stats, Takeover};
+
+/// When a headless run counts as done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StopCondition {
+    /// Some species has no cells left.
+    Extinct,
+    /// Fewer than this many cells changed in a tick.
+    Changes(u64),
+    /// The grid is exactly the same as at an earlier check, so it's going round in a loop
+    /// (or stuck, which is a loop of 1). Doesn't work with stochastic takeovers, where the same
+    /// grid can go somewhere different the next time round.
+    Repeat,
+    /// This many generations in.
+    Generation(u64),
+}
+
+impl std::fmt::Display for StopCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Extinct => f.write_str("extinct"),
+            Self::Changes(min) => write!(f, "changes:{min}"),
+            Self::Repeat => f.write_str("repeat"),
+            Self::Generation(generation) => write!(f, "generation:{generation}"),
+        }
+    }
+}
+
+impl std::str::FromStr for StopCondition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (s, None),
+        };
+        match (name, value) {
+            ("extinct", None) => Ok(Self::Extinct),
+            ("repeat", None) => Ok(Self::Repeat),
+            ("changes", Some(min)) => Ok(Self::Changes(
+                min.parse().with_context(|| format!("Bad cell count: {min}"))?,
+            )),
+            ("generation", Some(generation)) => Ok(Self::Generation(
+                generation
+                    .parse()
+                    .with_context(|| format!("Bad generation: {generation}"))?,
+            )),
+            _ => anyhow::bail!(
+                "Unknown stop condition: {s} (expected extinct, changes:N, repeat or generation:N)"
+            ),
+        }
+    }
+}
+
+impl StopCondition {
+    /// Why the run should stop at `generation`, if it should. `stats` are the counts at this
+    /// generation, `changes` the changed cells for every tick since the last check, and `repeat`
+    /// the earlier generation the grid is the same as, each only if something asked for them.
+    fn reason(
+        self,
+        generation: u64,
+        stats: Option<&Stats>,
+        changes: &[u64],
+        repeat: Option<u64>,
+    ) -> Option<String> {
+        match self {
+            Self::Extinct => stats
+                .and_then(|stats| stats.counts.iter().position(|&count| count == 0))
+                .map(|species| format!("species {species} is extinct")),
+            Self::Changes(min) => changes.iter().position(|&changed| changed < min).map(|i| {
+                let at = generation - changes.len() as u64 + i as u64 + 1;
+                format!("only {} cells changed in the tick to generation {at}", changes[i])
+            }),
+            // with --check-every that's a multiple of the actual period
+            Self::Repeat => repeat.map(|then| {
+                format!("same as generation {then}, a loop of {} generations", generation - then)
+            }),
+            Self::Generation(end) => {
+                (generation >= end).then(|| format!("reached generation {end}"))
+            }
+        }
+    }
+}
+
+/// What `--headless` does, see [`run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BatchOptions {
+    /// Run without a window instead of opening one.
+    pub headless: bool,
+    /// Stops at the first of these that's true.
+    pub stop_when: Vec<StopCondition>,
+    /// Only look every this many generations, checking costs a readback.
+    /// `changes` still sees every tick in between.
+    pub check_every: u32,
+}
+
+impl BatchOptions {
+    /// Catches conditions that can't work with `rule`.
+    pub fn check(&self, rule: &Rule) -> anyhow::Result<()> {
+        // the rolls depend on the generation, so coming back round to a grid isn't a loop
+        anyhow::ensure!(
+            !(self.stop_when.contains(&StopCondition::Repeat)
+                && matches!(rule.takeover, Takeover::Stochastic { .. })),
+            "--stop-when repeat doesn't work with --stochastic"
+        );
+        Ok(())
+    }
+
+    /// How many generations to run from `generation` before the next check.
+    /// Doesn't run past a generation it's meant to stop at.
+    fn ticks_until_check(&self, generation: u64) -> u64 {
+        self.stop_when
+            .iter()
+            .filter_map(|condition| match *condition {
+                StopCondition::Generation(end) if end > generation => Some(end - generation),
+                _ => None,
+            })
+            .fold(self.check_every as u64, u64::min)
+    }
+}
+
+/// Spots the grid coming back round to an earlier one, with Brent's cycle detection: every check
+/// only gets compared to one saved grid, which gets replaced after 1, 2, 4, 8... checks. That
+/// keeps one grid around however long the run goes, and still finds the exact length of the loop,
+/// just up to a couple of loops later than storing everything would.
+#[derive(Debug)]
+struct LoopFinder {
+    /// The grid (packed) that checks get compared to, and its generation.
+    saved: Option<(Vec<u32>, u64)>,
+    /// Checks since `saved` was, and how many until it gets replaced.
+    checks: u64,
+    limit: u64,
+}
+
+impl LoopFinder {
+    fn new() -> Self {
+        Self {
+            saved: None,
+            checks: 0,
+            limit: 1,
+        }
+    }
+
+    /// Returns the earlier generation `grid` is the same as, if it's the saved one.
+    fn check(&mut self, grid: Vec<u32>, generation: u64) -> Option<u64> {
+        if let Some((saved, then)) = &self.saved {
+            if *saved == grid {
+                return Some(*then);
+            }
+        }
+        self.checks += 1;
+        if self.checks >= self.limit {
+            self.saved = Some((grid, generation));
+            self.checks = 0;
+            self.limit *= 2;
+        }
+        None
+    }
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            headless: false,
+            stop_when: Vec::new(),
+            check_every: 1,
+        }
+    }
+}
+
+/// Runs the simulation without a window until one of the stop conditions is true, then logs
+/// which one and returns. Writes `--metrics` at every check on the way.
+pub(crate) async fn run(config: &Config) -> anyhow::Result<()> {
+    let options = &config.batch;
+    let Snapshot {
+        cells,
+        width,
+        height,
+        rule,
+        generation,
+        ..
+    } = config.start()?;
+    info!("Rule: {}", rule);
+    // a --load snapshot brings its own rule
+    options.check(&rule)?;
+    let mut metrics = match &config.metrics {
+        Some(path) => Some(MetricsWriter::create(path, rule.species)?),
+        None => None,
+    };
+    let mut sim = Simulation::with_state(cells, width, height, rule).await?;
+    sim.set_generation(generation);
+    sim.set_kernel(config.kernel);
+    let wants = |f: fn(&StopCondition) -> bool| options.stop_when.iter().any(f);
+    // only pay for what the conditions actually look at
+    // the metrics' changed column comes from the same count
+    sim.set_count_changes(metrics.is_some() || wants(|c| matches!(c, StopCondition::Changes(_))));
+    let count_species = metrics.is_some() || wants(|c| *c == StopCondition::Extinct);
+    let find_loops = wants(|c| *c == StopCondition::Repeat);
+    let stop_when: Vec<String> = options.stop_when.iter().map(|c| c.to_string()).collect();
+    info!("Running until {}", stop_when.join(" or "));
+    let mut loops = LoopFinder::new();
+    let mut last_log = Time::now();
+    loop {
+        let generation = sim.generation();
+        let stats = if count_species { Some(sim.stats()?) } else { None };
+        if let (Some(metrics), Some(stats)) = (&mut metrics, &stats) {
+            metrics.write(stats)?;
+        }
+        let repeat = if find_loops {
+            loops.check(sim.packed_grid()?, generation)
+        } else {
+            None
+        };
+        let changes = sim.changes();
+        let done = options
+            .stop_when
+            .iter()
+            .find_map(|condition| condition.reason(generation, stats.as_ref(), changes, repeat));
+        if let Some(reason) = done {
+            info!("Done at generation {generation}: {reason}");
+            return Ok(());
+        }
+        if Time::now().sub(&last_log) >= 1.0 {
+            match &stats {
+                Some(stats) => info!("{stats}"),
+                None => info!("generation {generation}"),
+            }
+            last_log = Time::now();
+        }
+        let ticks = options.ticks_until_check(generation);
+        sim.advance(ticks as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_conditions_round_trip() {
+        for condition in [
+            StopCondition::Extinct,
+            StopCondition::Changes(100),
+            StopCondition::Repeat,
+            StopCondition::Generation(0),
+            StopCondition::Generation(u64::MAX),
+        ] {
+            assert_eq!(condition.to_string().parse::<StopCondition>().unwrap(), condition);
+        }
+        assert_eq!("changes:0".parse::<StopCondition>().unwrap(), StopCondition::Changes(0));
+        for bad in [
+            "", "bogus", "extinct:1", "repeat:2", "changes", "changes:", "changes:-1", "generation",
+            "generation:x", "Extinct",
+        ] {
+            assert!(bad.parse::<StopCondition>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn stop_reasons() {
+        let stats = |counts: &[u64]| Stats {
+            generation: 10,
+            counts: counts.to_vec(),
+            changed: None,
+            tick_time: None,
+        };
+        let reason = |condition: StopCondition, stats: Option<&Stats>, changes: &[u64], repeat| {
+            condition.reason(10, stats, changes, repeat)
+        };
+        assert_eq!(
+            reason(StopCondition::Extinct, Some(&stats(&[5, 0, 3])), &[], None).unwrap(),
+            "species 1 is extinct"
+        );
+        assert_eq!(reason(StopCondition::Extinct, Some(&stats(&[5, 1, 3])), &[], None), None);
+        assert_eq!(reason(StopCondition::Extinct, None, &[], None), None);
+        // the changes are for the ticks to generations 8, 9 and 10
+        assert_eq!(
+            reason(StopCondition::Changes(50), None, &[80, 40, 10], None).unwrap(),
+            "only 40 cells changed in the tick to generation 9"
+        );
+        assert_eq!(reason(StopCondition::Changes(50), None, &[80, 50, 60], None), None);
+        assert_eq!(reason(StopCondition::Changes(50), None, &[], None), None);
+        assert_eq!(
+            reason(StopCondition::Repeat, None, &[], Some(4)).unwrap(),
+            "same as generation 4, a loop of 6 generations"
+        );
+        assert_eq!(reason(StopCondition::Repeat, None, &[], None), None);
+        assert_eq!(
+            reason(StopCondition::Generation(10), None, &[], None).unwrap(),
+            "reached generation 10"
+        );
+        assert_eq!(reason(StopCondition::Generation(11), None, &[], None), None);
+    }
+
+    #[test]
+    fn checks_stop_at_the_end() {
+        let options = BatchOptions {
+            headless: true,
+            stop_when: vec![StopCondition::Extinct, StopCondition::Generation(100)],
+            check_every: 30,
+        };
+        assert_eq!(options.ticks_until_check(0), 30);
+        assert_eq!(options.ticks_until_check(90), 10);
+        // past the end it's the other conditions' turn
+        assert_eq!(options.ticks_until_check(100), 30);
+    }
+
+    #[test]
+    fn finds_loops() {
+        // 5 generations in, then round a loop of 7
+        let grid = |generation: u64| {
+            let state = if generation < 5 { generation } else { 5 + (generation - 5) % 7 };
+            vec![state as u32; 3]
+        };
+        let mut loops = LoopFinder::new();
+        let (generation, then) = (0..1000)
+            .find_map(|generation| Some((generation, loops.check(grid(generation), generation)?)))
+            .unwrap();
+        assert_eq!(generation - then, 7);
+        assert_eq!(grid(generation), grid(then));
+        // a couple of loops late at most
+        assert!(generation < 5 + 7 * 4, "{generation}");
+        // stuck is a loop of 1
+        let mut loops = LoopFinder::new();
+        assert_eq!(loops.check(vec![1], 0), None);
+        assert_eq!(loops.check(vec![1], 1), Some(0));
+        // nothing ever repeats
+        let mut loops = LoopFinder::new();
+        for generation in 0..1000 {
+            assert_eq!(loops.check(vec![generation], generation.into()), None);
+        }
+    }
+
+    #[test]
+    fn repeat_needs_a_deterministic_rule() {
+        let options = BatchOptions {
+            headless: true,
+            stop_when: vec![StopCondition::Repeat],
+            check_every: 1,
+        };
+        assert!(options.check(&Rule::default()).is_ok());
+        let stochastic = Rule {
+            takeover: Takeover::Stochastic { probability: 0.5 },
+            ..Rule::default()
+        };
+        assert!(options.check(&stochastic).is_err());
+        let options = BatchOptions {
+            stop_when: vec![StopCondition::Extinct],
+            ..options
+        };
+        assert!(options.check(&stochastic).is_ok());
+    }
+}
@@ -0,0 +1,208 @@
+// grabbing frames off the gpu, for screenshots and recordings
+
+use std::sync::mpsc;
+
+use anyhow::Context;
+
+/// A frame off the gpu, tightly packed rgba.
+pub(crate) struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Frame {
+    pub fn png(&self) -> anyhow::Result<Vec<u8>> {
+        let image = image::RgbaImage::from_raw(self.width, self.height, self.rgba.clone())
+            .context("Frame is the wrong size")?;
+        // there's never any transparency, so don't bother saving it
+        let image = image::DynamicImage::ImageRgba8(image).into_rgb8();
+        let mut png = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut png, image::ImageFormat::Png)
+            .context("Couldn't encode png")?;
+        Ok(png.into_inner())
+    }
+}
+
+/// A frame on its way back from the gpu. Mapping is async (and on the web it has to be,
+/// blocking would never let it finish), so this gets checked every update until it's done.
+pub(crate) struct PendingFrame {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    /// Rows have to be padded to `COPY_BYTES_PER_ROW_ALIGNMENT` for the copy.
+    padded_row: u32,
+    bgra: bool,
+    mapped: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl PendingFrame {
+    /// Copies `texture` (which has to be 4 bytes per pixel) into a buffer and starts mapping it.
+    pub fn start(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<Self> {
+        let format = texture.format().remove_srgb_suffix();
+        let bgra = match format {
+            wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8Unorm => true,
+            _ => anyhow::bail!("Can't capture frames in {format:?}"),
+        };
+        let (width, height) = (texture.width(), texture.height());
+        let padded_row = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: padded_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+        let (tx, rx) = mpsc::channel();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, move |res| {
+            let _ = tx.send(res);
+        });
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            padded_row,
+            bgra,
+            mapped: rx,
+        })
+    }
+
+    /// The frame if it's made it back yet.
+    pub fn try_finish(&self, device: &wgpu::Device) -> Option<anyhow::Result<Frame>> {
+        // does nothing on the web, the browser maps things on its own
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = match self.mapped.try_recv() {
+            Ok(mapped) => mapped,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                return Some(Err(anyhow::anyhow!("Capture was never mapped")))
+            }
+        };
+        if let Err(e) = mapped {
+            return Some(Err(e).context("Failed to map capture buffer"));
+        }
+        let data = self.buffer.slice(..).get_mapped_range();
+        let mut rgba = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for row in data.chunks(self.padded_row as usize) {
+            for pixel in row[..(self.width * 4) as usize].chunks(4) {
+                if self.bgra {
+                    rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                } else {
+                    rgba.extend_from_slice(pixel);
+                }
+            }
+        }
+        drop(data);
+        self.buffer.unmap();
+        Some(Ok(Frame {
+            width: self.width,
+            height: self.height,
+            rgba,
+        }))
+    }
+
+    /// Blocks until the frame is back. Only works on native, the web never finishes while blocked.
+    pub fn wait(&self, device: &wgpu::Device) -> anyhow::Result<Frame> {
+        device.poll(wgpu::Maintain::Wait);
+        self.try_finish(device).context("Capture didn't finish")?
+    }
+}
+
+/// The time like `20240131-235959`, for file names. Local time on the web, utc on native.
+pub(crate) fn timestamp() -> String {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            let now = js_sys::Date::new_0();
+            format!(
+                "{:04}{:02}{:02}-{:02}{:02}{:02}",
+                now.get_full_year(),
+                now.get_month() + 1,
+                now.get_date(),
+                now.get_hours(),
+                now.get_minutes(),
+                now.get_seconds()
+            )
+        } else {
+            // there's no timezone info without another crate, so this is utc
+            let secs = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let (days, secs) = (secs / 86400, secs % 86400);
+            let (year, month, day) = civil_from_days(days as i64);
+            format!(
+                "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60
+            )
+        }
+    }
+}
+
+// days since 1970-01-01 to a date, from http://howardhinnant.github.io/date_algorithms.html
+#[cfg(not(target_arch = "wasm32"))]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Writes a file to the working directory on native, or hands it to the browser as a download.
+pub(crate) fn save_file(name: &str, bytes: &[u8], mime: &str) -> anyhow::Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            use wasm_bindgen::JsCast;
+            let js_err = |e: wasm_bindgen::JsValue| anyhow::anyhow!("{e:?}");
+            let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+            let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+                &parts,
+                web_sys::BlobPropertyBag::new().type_(mime),
+            )
+            .map_err(js_err)?;
+            let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_err)?;
+            let link = web_sys::window()
+                .and_then(|win| win.document())
+                .context("No document")?
+                .create_element("a")
+                .map_err(js_err)?
+                .dyn_into::<web_sys::HtmlAnchorElement>()
+                .map_err(|_| anyhow::anyhow!("<a> isn't an anchor?"))?;
+            link.set_href(&url);
+            link.set_download(name);
+            link.click();
+            web_sys::Url::revoke_object_url(&url).map_err(js_err)?;
+            Ok(())
+        } else {
+            let _ = mime;
+            std::fs::write(name, bytes).with_context(|| format!("Couldn't write {name}"))
+        }
+    }
+}
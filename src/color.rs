@@ -0,0 +1,277 @@
+use rand::Rng;
+
+/// A way of picking a colour for each species, for however many species there are.
+#[derive(Debug, Clone, Copy)]
+enum Palette {
+    /// Evenly spaced around the colour wheel, starting at `offset` degrees.
+    Hues { offset: f64, value: f64 },
+    /// Black to white.
+    Grayscale,
+}
+
+impl Palette {
+    fn colors(self, species: u32) -> Vec<Color> {
+        (0..species)
+            .map(|i| match self {
+                Self::Hues { offset, value } => {
+                    Color::from_hsv(offset + 360.0 * i as f64 / species as f64, value)
+                }
+                Self::Grayscale => {
+                    let v = i as f64 / (species - 1).max(1) as f64;
+                    [v, v, v].into()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Every color mode, in the order the arrow keys go through them.
+/// With 3 species these come out to exactly red/green/blue, cyan/magenta/yellow and so on.
+const PALETTES: [Palette; 4] = [
+    // standard RGB
+    Palette::Hues { offset: 0.0, value: 1.0 },
+    // CMY
+    Palette::Hues { offset: 180.0, value: 1.0 },
+    // darker rgb
+    Palette::Hues { offset: 0.0, value: 0.8 },
+    // grayscale
+    Palette::Grayscale,
+];
+/// How many color modes there are.
+pub(crate) const COLOR_MODES: usize = PALETTES.len();
+
+pub(crate) struct ColorModes {
+    mode: usize,
+    colors: Box<[Vec<Color>]>,
+    blender: ColorBlender,
+    blender_enabled: bool,
+    should_push: bool,
+}
+
+impl ColorModes {
+    pub fn new(species: u32) -> Self {
+        Self::with_mode(species, 0)
+    }
+    /// Starts on color mode `mode` instead of the first, wrapping round past the last one.
+    pub fn with_mode(species: u32, mode: usize) -> Self {
+        Self {
+            mode: mode % COLOR_MODES,
+            blender: ColorBlender::new(0.01),
+            colors: PALETTES.iter().map(|p| p.colors(species)).collect(),
+            blender_enabled: false,
+            should_push: true
+        }
+    }
+    pub fn tick(&mut self) {
+        if self.blender_enabled {
+            self.blender.step();
+        } else {
+            self.blender.reset(&self.colors[self.mode]);
+            self.should_push = true;
+        }
+    }
+    /// Flat rgb triples, one per species, which is what `colors` in the shader wants.
+    pub fn colors(&self) -> Vec<f32> {
+        if self.blender_enabled {
+            self.blender.colors()
+        } else {
+            flatten(&self.colors[self.mode])
+        }
+    }
+    pub fn push_to_gpu(&mut self, q: &wgpu::Queue, buffer: &wgpu::Buffer) {
+        if self.blender_enabled { 
+            self.blender.push_to_gpu(q, buffer);
+        } else if self.should_push {
+            self.should_push = false; // we should only push once per change
+            // this probably doesn't save any meaningful amount of time but i really dont care
+            q.write_buffer(buffer, 0, bytemuck::cast_slice(&self.colors()));
+        }
+    }
+
+    pub fn next(&mut self) {
+        self.mode = (self.mode + 1) % self.colors.len();
+        self.should_push = true;
+    }
+    pub fn prev(&mut self) {
+        self.mode = (self.mode + self.colors.len() - 1) % self.colors.len();
+        self.should_push = true;
+    }
+    pub fn toggle_blender(&mut self) {
+        self.blender_enabled = !self.blender_enabled;
+    }
+    
+}
+
+fn flatten(colors: &[Color]) -> Vec<f32> {
+    colors.iter().flat_map(|c| c.color()).collect()
+}
+
+
+struct ColorBlender {
+    colors: Vec<Color>,
+    targets: Vec<Color>,
+    dirty: bool,
+    has_done_step: bool,
+    pub blend_amt: f64,
+}
+
+impl ColorBlender {
+    fn new(blend_amt: f64) -> Self {
+        Self {
+            colors: Vec::new(),
+            targets: Vec::new(),
+            dirty: false,
+            has_done_step: false,
+            blend_amt
+        }
+    }
+    fn colors(&self) -> Vec<f32> {
+        flatten(&self.colors)
+    }
+    fn reset(&mut self, to: &[Color]) {
+        self.colors = to.to_vec();
+        self.targets = to.to_vec();
+        if self.has_done_step {
+            self.has_done_step = false;
+            self.dirty = true;
+        }
+    }
+    fn step(&mut self) {
+        self.has_done_step = true;
+        for (color, target) in self.colors.iter_mut().zip(&mut self.targets) {
+            // if we've hit a target, reset to new random target
+            if (*color - *target).abs().sum() < self.blend_amt {
+                *target = target.rand_within(0.5)
+            }
+            // blend towards target`
+            color.blend_to_target(target, self.blend_amt);
+        }
+        self.dirty = true;
+    }
+    fn push_to_gpu(&self, q: &wgpu::Queue, buffer: &wgpu::Buffer) {
+        if self.dirty {
+            let colors = self.colors();
+            q.write_buffer(buffer, 0, bytemuck::cast_slice(&colors));
+        }
+    }
+}
+
+
+// COLOR MATH AAAAAAAA
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Color {
+    r: f64,
+    g: f64,
+    b: f64,
+}
+
+impl Color {
+    fn new(r: f64, g: f64, b: f64) -> Self {
+        Self { r, g, b }
+    }
+    /// Fully saturated colour at `hue` degrees.
+    fn from_hsv(hue: f64, value: f64) -> Self {
+        let h = hue.rem_euclid(360.0) / 60.0;
+        let f = h - h.floor();
+        let (v, q, t) = (value, value * (1.0 - f), value * f);
+        match h as u32 {
+            0 => Self::new(v, t, 0.0),
+            1 => Self::new(q, v, 0.0),
+            2 => Self::new(0.0, v, t),
+            3 => Self::new(0.0, q, v),
+            4 => Self::new(t, 0.0, v),
+            _ => Self::new(v, 0.0, q),
+        }
+    }
+    pub fn color(&self) -> [f32; 3] {
+        [
+            self.r as f32, 
+            self.g as f32, 
+            self.b as f32
+        ]
+    }
+    fn abs(self) -> Self {
+        Self {
+            r: self.r.abs(),
+            g: self.g.abs(),
+            b: self.b.abs(),
+        }
+    }
+    /*fn rand() -> Self {
+        let rng = &mut rand::thread_rng();
+        Self {
+            r: rng.gen_range(0.0..=1.0f64),
+            g: rng.gen_range(0.0..=1.0f64), 
+            b: rng.gen_range(0.0..=1.0f64),
+        }
+    
+    }*/
+    fn rand_within(&self, amt: f64) -> Self {
+        let r_min = (self.r - amt).clamp(0.0, 1.0);
+        let r_max = (self.r + amt).clamp(0.0, 1.0);
+        let g_min = (self.g - amt).clamp(0.0, 1.0);
+        let g_max = (self.g + amt).clamp(0.0, 1.0);
+        let b_min = (self.b - amt).clamp(0.0, 1.0);
+        let b_max = (self.b + amt).clamp(0.0, 1.0);
+        let rng = &mut rand::thread_rng();
+        Self {
+            r: rng.gen_range(r_min..=r_max),
+            g: rng.gen_range(g_min..=g_max),
+            b: rng.gen_range(b_min..=b_max),
+        }
+    }
+    fn sum(self) -> f64 {
+        self.r + self.g + self.b
+    }
+    fn blend_to_target(&mut self, target: &Self, amt: f64) {
+        // sub/add by amt for self to get closer (unless we're < amt away)
+        let diff_r = target.r - self.r;
+        let diff_g = target.g - self.g;
+        let diff_b = target.b - self.b;
+        if diff_r.abs() < amt {
+            self.r = target.r;
+        } else {
+            self.r += diff_r.signum() * amt;
+        }
+        if diff_g.abs() < amt {
+            self.g = target.g;
+        } else {
+            self.g += diff_g.signum() * amt;
+        }
+        if diff_b.abs() < amt {
+            self.b = target.b;
+        } else {
+            self.b += diff_b.signum() * amt;
+        }
+    }
+}
+
+impl From<[f64; 3]> for Color {
+    fn from(arr: [f64; 3]) -> Self {
+        Self {
+            r: arr[0],
+            g: arr[1],
+            b: arr[2],
+        }
+    }
+}
+
+impl std::ops::Sub for Color {
+    type Output = Color;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Color::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
+impl std::ops::Sub<&Color> for Color {
+    type Output = Color;
+    fn sub(self, rhs: &Self) -> Self::Output {
+        Color::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
+
+impl std::ops::Sub<&Color> for &Color {
+    type Output = Color;
+    fn sub(self, rhs: &Color) -> Self::Output {
+        Color::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
\ No newline at end of file
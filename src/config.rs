@@ -0,0 +1,462 @@
+use anyhow::Context;
+use tracing::info;
+
+use crate::{
+    batch::BatchOptions, color::COLOR_MODES, export::ExportOptions, record::RecordOptions, Dominance, InitialState,
+    Kernel, Rule, Snapshot, Takeover,
+};
+
+/// Grid size used when nothing else is asked for, one cell per pixel on a 1080p screen.
+pub const DEFAULT_WIDTH: u32 = 1920;
+pub const DEFAULT_HEIGHT: u32 = 1080;
+/// Longest a grid's side can be, anything bigger wouldn't fit in a storage buffer anyway.
+pub const MAX_SIDE: u32 = 1 << 15;
+
+/// Startup options for the windowed game.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub width: u32,
+    pub height: u32,
+    pub rule: Rule,
+    pub kernel: Kernel,
+    /// Seeds the starting noise, random unless asked for.
+    pub seed: u64,
+    pub init: InitialState,
+    /// Snapshot to start from instead, see [`crate::Snapshot`].
+    pub load: Option<std::path::PathBuf>,
+    /// What v records.
+    pub record: RecordOptions,
+    /// How often to count the species, see [`crate::Stats`].
+    pub stats_every: u64,
+    /// Where to write every count to, see `MetricsWriter`.
+    pub metrics: Option<std::path::PathBuf>,
+    /// Writing an animation instead of opening a window.
+    pub export: ExportOptions,
+    /// Running without a window until something happens.
+    pub batch: BatchOptions,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            rule: Rule::default(),
+            kernel: Kernel::default(),
+            seed: rand::random(),
+            init: InitialState::default(),
+            load: None,
+            record: RecordOptions::default(),
+            stats_every: 1,
+            metrics: None,
+            export: ExportOptions::default(),
+            batch: BatchOptions::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads options from the command line.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_args() -> anyhow::Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// Reads options from the url, there's no command line on the web.
+    /// `?seed=42&species=5` is the same as `--seed 42 --species 5`.
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_args() -> anyhow::Result<Self> {
+        let search = web_sys::window()
+            .context("No window")?
+            .location()
+            .search()
+            .map_err(|e| anyhow::anyhow!("Couldn't read the url: {e:?}"))?;
+        let args = query_args(&search, |value| {
+            js_sys::decode_uri_component(value)
+                .map(String::from)
+                .map_err(|e| anyhow::anyhow!("{e:?}"))
+        })?;
+        Self::parse(args.into_iter())
+    }
+
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--size" => {
+                    let size = args.next().context("--size needs a value, like 1920x1080")?;
+                    (config.width, config.height) = parse_size(&size)?;
+                }
+                "--neighbourhood" => {
+                    let neighbourhood = args
+                        .next()
+                        .context("--neighbourhood needs a value, like moore or hex")?;
+                    config.rule.neighbourhood = neighbourhood.parse()?;
+                }
+                "--boundary" => {
+                    let boundary = args
+                        .next()
+                        .context("--boundary needs a value, like clipped or toroidal")?;
+                    config.rule.boundary = boundary.parse()?;
+                }
+                "--species" => {
+                    let species = args.next().context("--species needs a number")?;
+                    config.rule.species = species
+                        .parse()
+                        .with_context(|| format!("Bad species count: {species}"))?;
+                }
+                "--dominance" => {
+                    let dominance: Dominance = args
+                        .next()
+                        .context("--dominance needs a matrix, like 0,1,0;0,0,1;1,0,0")?
+                        .parse()?;
+                    // a matrix already says how many species there are
+                    if let Some(species) = dominance.species() {
+                        config.rule.species = species;
+                    }
+                    config.rule.dominance = dominance;
+                }
+                "--threshold" => {
+                    let threshold = args.next().context("--threshold needs a number")?;
+                    config.rule.threshold = Some(
+                        threshold
+                            .parse()
+                            .with_context(|| format!("Bad threshold: {threshold}"))?,
+                    );
+                }
+                "--stochastic" => {
+                    let probability = args
+                        .next()
+                        .context("--stochastic needs a probability, like 0.5")?;
+                    let probability: f64 = probability
+                        .parse()
+                        .with_context(|| format!("Bad probability: {probability}"))?;
+                    anyhow::ensure!(
+                        (0.0..=1.0).contains(&probability),
+                        "Probability has to be between 0 and 1, got {probability}"
+                    );
+                    config.rule.takeover = Takeover::Stochastic { probability };
+                }
+                "--init" => {
+                    let init = args
+                        .next()
+                        .context("--init needs a value, like noise or voronoi")?;
+                    config.init = init.parse()?;
+                }
+                "--load" => {
+                    let path = args.next().context("--load needs a snapshot file")?;
+                    config.load = Some(path.into());
+                }
+                "--seed" => {
+                    let seed = args.next().context("--seed needs a number")?;
+                    config.seed = seed.parse().with_context(|| format!("Bad seed: {seed}"))?;
+                }
+                "--kernel" => {
+                    let kernel = args.next().context("--kernel needs a value, naive or tiled")?;
+                    config.kernel = kernel.parse()?;
+                }
+                "--record-format" => {
+                    let format = args.next().context("--record-format needs a value, png or y4m")?;
+                    config.record.format = format.parse()?;
+                }
+                "--record-every" => {
+                    let every = args.next().context("--record-every needs a number")?;
+                    config.record.every = every
+                        .parse()
+                        .with_context(|| format!("Bad generation count: {every}"))?;
+                    anyhow::ensure!(config.record.every > 0, "--record-every can't be zero");
+                }
+                "--record-downscale" => {
+                    let downscale = args.next().context("--record-downscale needs a number")?;
+                    config.record.downscale = downscale
+                        .parse()
+                        .with_context(|| format!("Bad downscale: {downscale}"))?;
+                    anyhow::ensure!(config.record.downscale > 0, "--record-downscale can't be zero");
+                }
+                "--stats-every" => {
+                    let every = args.next().context("--stats-every needs a number")?;
+                    config.stats_every = every
+                        .parse()
+                        .with_context(|| format!("Bad generation count: {every}"))?;
+                    anyhow::ensure!(config.stats_every > 0, "--stats-every can't be zero");
+                }
+                "--metrics" => {
+                    let path = args.next().context("--metrics needs a .csv or .ndjson file")?;
+                    config.metrics = Some(path.into());
+                }
+                "--export" => {
+                    let path = args.next().context("--export needs a .gif or .png file")?;
+                    config.export.path = Some(path.into());
+                }
+                "--export-range" => {
+                    let range = args.next().context("--export-range needs a value, like 0..300")?;
+                    config.export.range = parse_range(&range)?;
+                }
+                "--export-delay" => {
+                    let delay = args.next().context("--export-delay needs a number of milliseconds")?;
+                    config.export.delay =
+                        delay.parse().with_context(|| format!("Bad delay: {delay}"))?;
+                    anyhow::ensure!(config.export.delay > 0, "--export-delay can't be zero");
+                }
+                "--export-downscale" => {
+                    let downscale = args.next().context("--export-downscale needs a number")?;
+                    config.export.downscale = downscale
+                        .parse()
+                        .with_context(|| format!("Bad downscale: {downscale}"))?;
+                    anyhow::ensure!(config.export.downscale > 0, "--export-downscale can't be zero");
+                }
+                "--export-palette" => {
+                    let palette = args.next().context("--export-palette needs a color mode number")?;
+                    config.export.palette = palette
+                        .parse()
+                        .with_context(|| format!("Bad color mode: {palette}"))?;
+                    anyhow::ensure!(
+                        config.export.palette < COLOR_MODES,
+                        "There are only {COLOR_MODES} color modes, from 0 to {}",
+                        COLOR_MODES - 1
+                    );
+                }
+                "--headless" => config.batch.headless = true,
+                "--stop-when" => {
+                    let conditions = args
+                        .next()
+                        .context("--stop-when needs a condition, like extinct or changes:100")?;
+                    for condition in conditions.split(',') {
+                        config.batch.stop_when.push(condition.trim().parse()?);
+                    }
+                }
+                "--check-every" => {
+                    let every = args.next().context("--check-every needs a number")?;
+                    config.batch.check_every = every
+                        .parse()
+                        .with_context(|| format!("Bad generation count: {every}"))?;
+                    anyhow::ensure!(config.batch.check_every > 0, "--check-every can't be zero");
+                }
+                _ => anyhow::bail!("Unknown argument: {arg}"),
+            }
+        }
+        config.rule.check()?;
+        config.init.check(config.rule.species)?;
+        anyhow::ensure!(
+            cfg!(not(target_arch = "wasm32")) || config.export.path.is_none(),
+            "Exporting only works from the command line"
+        );
+        anyhow::ensure!(
+            cfg!(not(target_arch = "wasm32")) || config.metrics.is_none(),
+            "Metrics only work from the command line"
+        );
+        anyhow::ensure!(
+            cfg!(not(target_arch = "wasm32")) || !config.batch.headless,
+            "Headless runs only work from the command line"
+        );
+        // without a window there'd be no way to stop it
+        anyhow::ensure!(
+            config.batch.headless != config.batch.stop_when.is_empty(),
+            "--headless and --stop-when go together"
+        );
+        config.batch.check(&config.rule)?;
+        Ok(config)
+    }
+
+    /// Where a run without a window starts, the `--load` snapshot or else a freshly generated grid.
+    pub fn start(&self) -> anyhow::Result<Snapshot> {
+        match &self.load {
+            Some(path) => {
+                let snapshot = Snapshot::load(path)?;
+                info!("Loaded snapshot from {}", path.display());
+                Ok(snapshot)
+            }
+            None => {
+                let cells = self
+                    .init
+                    .generate(self.width, self.height, self.rule.species, self.seed)?;
+                info!("Initial state: {}", self.init);
+                info!("Seed: {}", self.seed);
+                Ok(Snapshot {
+                    width: self.width,
+                    height: self.height,
+                    rule: self.rule.clone(),
+                    seed: self.seed,
+                    init: self.init.clone(),
+                    generation: 0,
+                    cells,
+                })
+            }
+        }
+    }
+}
+
+/// Turns `?seed=42&species=5` into `--seed 42 --species 5`, decoding every value with `decode`.
+/// A key without an `=`, like `?headless`, is a switch and becomes just `--headless`.
+#[cfg(any(target_arch = "wasm32", test))]
+fn query_args(
+    search: &str,
+    decode: impl Fn(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    for pair in search.trim_start_matches('?').split('&').filter(|p| !p.is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            args.push(format!("--{pair}"));
+            continue;
+        };
+        let value = decode(value).with_context(|| format!("Bad url value for {key}"))?;
+        args.push(format!("--{key}"));
+        args.push(value);
+    }
+    Ok(args)
+}
+
+fn parse_size(size: &str) -> anyhow::Result<(u32, u32)> {
+    let (width, height) = size
+        .split_once('x')
+        .with_context(|| format!("Size should look like 1920x1080, got {size}"))?;
+    let width = width.parse().with_context(|| format!("Bad width: {width}"))?;
+    let height = height.parse().with_context(|| format!("Bad height: {height}"))?;
+    check_size(width, height)?;
+    Ok((width, height))
+}
+
+/// Makes sure a grid size is one `--size` would take, for sizes that come from somewhere else too.
+pub(crate) fn check_size(width: u32, height: u32) -> anyhow::Result<()> {
+    anyhow::ensure!(width > 0 && height > 0, "Size can't be zero");
+    anyhow::ensure!(
+        width <= MAX_SIDE && height <= MAX_SIDE,
+        "Size can be at most {MAX_SIDE}x{MAX_SIDE}, got {width}x{height}"
+    );
+    Ok(())
+}
+
+fn parse_range(range: &str) -> anyhow::Result<std::ops::Range<u64>> {
+    let (start, end) = range
+        .split_once("..")
+        .with_context(|| format!("Range should look like 0..300, got {range}"))?;
+    let start = start.parse().with_context(|| format!("Bad generation: {start}"))?;
+    let end = end.parse().with_context(|| format!("Bad generation: {end}"))?;
+    anyhow::ensure!(start < end, "Range {range} is empty");
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::StopCondition;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Config> {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1920x1080").unwrap(), (1920, 1080));
+        assert_eq!(parse_size("1x1").unwrap(), (1, 1));
+        assert_eq!(parse_size("32768x1").unwrap(), (MAX_SIDE, 1));
+        for bad in [
+            "1920", "0x1080", "1920x0", "x", "1920x", "-1x5", "1920*1080", "1x2x3", "32769x1", "1x99999",
+        ] {
+            assert!(parse_size(bad).is_err(), "{bad}");
+        }
+        let config = parse(&["--size", "64x48"]).unwrap();
+        assert_eq!((config.width, config.height), (64, 48));
+        assert!(parse(&["--size"]).is_err());
+    }
+
+    #[test]
+    fn defaults() {
+        let config = parse(&[]).unwrap();
+        assert_eq!((config.width, config.height), (DEFAULT_WIDTH, DEFAULT_HEIGHT));
+        assert_eq!(config.rule, Rule::default());
+        assert_eq!(config.init, InitialState::default());
+    }
+
+    #[test]
+    fn rule_options() {
+        let config = parse(&[
+            "--species", "4", "--neighbourhood", "hex", "--boundary", "fixed:2", "--threshold", "3",
+            "--stochastic", "0.5", "--seed", "42",
+        ])
+        .unwrap();
+        assert_eq!(config.rule.species, 4);
+        assert_eq!(config.rule.neighbourhood, crate::Neighbourhood::Hexagonal);
+        assert_eq!(config.rule.boundary, crate::Boundary::Fixed(2));
+        assert_eq!(config.rule.threshold, Some(3));
+        assert_eq!(config.rule.takeover, Takeover::Stochastic { probability: 0.5 });
+        assert_eq!(config.seed, 42);
+        // checked against the rule once everything's read
+        assert!(parse(&["--species", "2", "--boundary", "fixed:2"]).is_err());
+        assert!(parse(&["--stochastic", "1.5"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+
+    #[test]
+    fn dominance_sets_the_species() {
+        let config = parse(&["--dominance", "0,1,0,1;0,0,1,0;1,0,0,1;0,1,0,0"]).unwrap();
+        assert_eq!(config.rule.species, 4);
+        // whichever comes last wins, and then they have to agree
+        assert!(parse(&["--dominance", "0,1;1,0", "--species", "3"]).is_err());
+        assert_eq!(parse(&["--species", "3", "--dominance", "0,1;1,0"]).unwrap().rule.species, 2);
+        assert!(parse(&["--dominance", "0,1,0;0,0,1"]).is_err());
+        // weights that would overflow the scores
+        assert!(parse(&["--dominance", "0,4000000000,0;0,0,1;1,0,0"]).is_err());
+        assert!(parse(&["--dominance", "0,100000000,0;0,0,1;1,0,0"]).is_err());
+    }
+
+    #[test]
+    fn export_options() {
+        let config = parse(&[
+            "--export", "ow.gif", "--export-range", "10..20", "--export-downscale", "2", "--export-palette", "3",
+        ])
+        .unwrap();
+        assert_eq!(config.export.path, Some("ow.gif".into()));
+        assert_eq!(config.export.range, 10..20);
+        assert_eq!(config.export.downscale, 2);
+        assert_eq!(config.export.palette, 3);
+        assert_eq!(parse(&[]).unwrap().export.palette, 0);
+        assert!(parse(&["--export-palette", "4"]).is_err());
+        assert!(parse(&["--export-palette", "-1"]).is_err());
+        assert!(parse(&["--export-range", "20..10"]).is_err());
+    }
+
+    #[test]
+    fn batch_options() {
+        let config = parse(&[
+            "--headless", "--stop-when", "extinct, changes:10,generation:500", "--check-every", "5",
+        ])
+        .unwrap();
+        assert!(config.batch.headless);
+        assert_eq!(
+            config.batch.stop_when,
+            [StopCondition::Extinct, StopCondition::Changes(10), StopCondition::Generation(500)]
+        );
+        assert_eq!(config.batch.check_every, 5);
+        assert!(parse(&["--headless"]).is_err());
+        assert!(parse(&["--stop-when", "extinct"]).is_err());
+        assert!(parse(&["--headless", "--stop-when", "extinct,bogus"]).is_err());
+        assert!(parse(&["--headless", "--stop-when", "extinct", "--check-every", "0"]).is_err());
+        assert!(parse(&["--headless", "--stop-when", "repeat", "--stochastic", "0.5"]).is_err());
+    }
+
+    #[test]
+    fn url_parameters() {
+        let decode = |value: &str| Ok(value.replace("%3A", ":"));
+        let args = query_args("?seed=42&species=5&init=voronoi%3A8", decode).unwrap();
+        assert_eq!(args, ["--seed", "42", "--species", "5", "--init", "voronoi:8"]);
+        let config = Config::parse(args.into_iter()).unwrap();
+        assert_eq!(config.seed, 42);
+        assert_eq!(config.rule.species, 5);
+        assert_eq!(config.init, InitialState::Voronoi { seeds: 8 });
+        assert!(query_args("", decode).unwrap().is_empty());
+        assert!(query_args("?", decode).unwrap().is_empty());
+        // a key on its own is a switch
+        let args = query_args("?headless&stop-when=extinct", decode).unwrap();
+        assert_eq!(args, ["--headless", "--stop-when", "extinct"]);
+        assert!(Config::parse(args.into_iter()).unwrap().batch.headless);
+        let args = query_args("?seed=7&headless", decode).unwrap();
+        assert_eq!(args, ["--seed", "7", "--headless"]);
+        // which is an error for anything that needs a value, and so is an empty one
+        assert_eq!(query_args("?size", decode).unwrap(), ["--size"]);
+        assert!(Config::parse(query_args("?size", decode).unwrap().into_iter()).is_err());
+        assert_eq!(query_args("?size=", decode).unwrap(), ["--size", ""]);
+        assert!(Config::parse(query_args("?size=", decode).unwrap().into_iter()).is_err());
+        assert!(query_args("?size=%", |_| anyhow::bail!("bad escape")).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+// cpu version of the compute shader
+// this has to match `compute` in shader.wgsl exactly, it's what we check the gpu against
+
+use crate::{
+    rule::{roll, Neighbour, ODDS_ONE},
+    Rule, MAX_SPECIES,
+};
+
+/// Advances the grid by one generation on the cpu, using the original rule.
+///
+/// `input` and `output` are row-major `width * height` grids with one `u32` per cell
+/// (the gpu packs them, see [`crate::CellLayout`]).
+/// Mirrors the `compute` shader: every cell looks at its 8 neighbours (cells past the edge
+/// are skipped) and becomes whatever beats it if more than 2 of them do.
+///
+/// With other rules every species gets a score from how hard its neighbours beat the cell,
+/// and the best scoring one (the lowest species on a tie) takes over if it beats the threshold.
+pub fn step(input: &[u32], output: &mut [u32], width: u32, height: u32) {
+    step_with_rule(input, output, width, height, &Rule::default(), 0);
+}
+
+/// Same as [`step`], but with any [`Rule`].
+///
+/// `generation` is the number of the generation being stepped from, it seeds stochastic takeovers.
+///
+/// # Panics
+///
+/// If the rule doesn't pass [`Rule::check`], or either grid isn't `width * height` cells.
+pub fn step_with_rule(
+    input: &[u32],
+    output: &mut [u32],
+    width: u32,
+    height: u32,
+    rule: &Rule,
+    generation: u32,
+) {
+    if let Err(e) = rule.check() {
+        panic!("can't step with a bad rule: {e}");
+    }
+    let (width, height) = (width as usize, height as usize);
+    assert_eq!(input.len(), width * height, "input is the wrong size for the grid");
+    assert_eq!(output.len(), width * height, "output is the wrong size for the grid");
+    let threshold = rule.threshold();
+    let odds = rule.odds();
+    let species = rule.species as usize;
+    let dominance = rule.dominance_matrix();
+    // the neighbourhood only depends on whether the row is odd (for hex grids)
+    let offsets = [rule.neighbourhood.offsets(0), rule.neighbourhood.offsets(1)];
+    for y in 0..height {
+        for x in 0..width {
+            let us = input[x + y * width];
+            let mut scores = [0u32; MAX_SPECIES as usize];
+            for &(x_o, y_o) in &offsets[y % 2] {
+                let them = match rule.boundary.resolve(
+                    x as i32 + x_o,
+                    y as i32 + y_o,
+                    width as u32,
+                    height as u32,
+                ) {
+                    Neighbour::Cell(t_x, t_y) => input[t_x as usize + t_y as usize * width],
+                    Neighbour::Border(species) => species,
+                    Neighbour::Missing => continue,
+                };
+                if (them as usize) < species && (us as usize) < species {
+                    scores[them as usize] += dominance[them as usize * species + us as usize];
+                }
+            }
+            // whoever beats us the most gets a shot at taking over, ties go to the lower species
+            let mut wins_against_us = 0;
+            let mut win_count = 0;
+            for (them, &score) in scores.iter().enumerate().take(species) {
+                if score > win_count {
+                    wins_against_us = them as u32;
+                    win_count = score;
+                }
+            }
+            let index = x + y * width;
+            let mut taken_over = win_count > threshold;
+            // only roll when it can actually fail, same as the shader
+            if taken_over && odds[win_count as usize] < ODDS_ONE {
+                taken_over = roll(index as u32, generation) < odds[win_count as usize];
+            }
+            output[index] = if taken_over { wins_against_us } else { us };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Boundary;
+
+    /// Species 0 everywhere but the right column, which is all 1 (and 1 beats 0).
+    #[rustfmt::skip]
+    const RIGHT_COLUMN: [u32; 16] = [
+        0, 0, 0, 1,
+        0, 0, 0, 1,
+        0, 0, 0, 1,
+        0, 0, 0, 1,
+    ];
+
+    fn step_4x4(boundary: Boundary) -> Vec<u32> {
+        let rule = Rule {
+            boundary,
+            ..Rule::default()
+        };
+        let mut output = vec![0; 16];
+        step_with_rule(&RIGHT_COLUMN, &mut output, 4, 4, &rule, 0);
+        output
+    }
+
+    #[test]
+    fn clipped() {
+        // only the middle of the column next to it has 3 neighbours of species 1
+        #[rustfmt::skip]
+        let expected = [
+            0, 0, 0, 1,
+            0, 0, 1, 1,
+            0, 0, 1, 1,
+            0, 0, 0, 1,
+        ];
+        assert_eq!(step_4x4(Boundary::Clipped), expected);
+        // nothing out there beats anything
+        assert_eq!(step_4x4(Boundary::Fixed(0)), expected);
+    }
+
+    #[test]
+    fn toroidal() {
+        // the left column wraps round to the right one, and the corners wrap top to bottom
+        #[rustfmt::skip]
+        let expected = [
+            1, 0, 1, 1,
+            1, 0, 1, 1,
+            1, 0, 1, 1,
+            1, 0, 1, 1,
+        ];
+        assert_eq!(step_4x4(Boundary::Toroidal), expected);
+    }
+
+    #[test]
+    fn reflective() {
+        // the corners see their own row again past the edge, the left column only sees 0s
+        #[rustfmt::skip]
+        let expected = [
+            0, 0, 1, 1,
+            0, 0, 1, 1,
+            0, 0, 1, 1,
+            0, 0, 1, 1,
+        ];
+        assert_eq!(step_4x4(Boundary::Reflective), expected);
+    }
+
+    #[test]
+    fn fixed() {
+        // everything along the edge has at least 3 neighbours of species 1 past it
+        #[rustfmt::skip]
+        let expected = [
+            1, 1, 1, 1,
+            1, 0, 1, 1,
+            1, 0, 1, 1,
+            1, 1, 1, 1,
+        ];
+        assert_eq!(step_4x4(Boundary::Fixed(1)), expected);
+    }
+
+    #[test]
+    fn dominance_weights() {
+        // 1 beats 0 three times as hard, so a single neighbour is enough
+        let heavy = Rule {
+            dominance: "0,0,0;3,0,0;0,0,0".parse().unwrap(),
+            ..Rule::default()
+        };
+        #[rustfmt::skip]
+        let input = [
+            0, 0, 0,
+            0, 1, 0,
+            0, 0, 0,
+        ];
+        let mut output = [0; 9];
+        step_with_rule(&input, &mut output, 3, 3, &heavy, 0);
+        assert_eq!(output, [1; 9]);
+        // nothing beats anything
+        let zeros = Rule {
+            dominance: "0,0,0;0,0,0;0,0,0".parse().unwrap(),
+            ..Rule::default()
+        };
+        let mut output = vec![0; 16];
+        step_with_rule(&RIGHT_COLUMN, &mut output, 4, 4, &zeros, 0);
+        assert_eq!(output, RIGHT_COLUMN);
+    }
+}
@@ -0,0 +1,307 @@
+// turning a run of generations into a gif or apng, headless
+
+use std::{borrow::Cow, fs::File, io::BufWriter, ops::Range, path::PathBuf};
+
+use anyhow::Context;
+use tracing::info;
+
+use crate::{color::ColorModes, config::Config, metrics::MetricsWriter, Simulation, Snapshot};
+
+/// What `--export` writes, see [`export`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExportOptions {
+    /// `.gif`, or `.png`/`.apng` for an animated png. `None` runs the game like normal.
+    pub path: Option<PathBuf>,
+    /// Which generations go in, end not included.
+    pub range: Range<u64>,
+    /// How long each frame shows for, in milliseconds.
+    pub delay: u32,
+    /// Shrink frames by this much each way, picking cells like the recorder does.
+    pub downscale: u32,
+    /// Which color mode to draw with, like pressing the right arrow this many times.
+    pub palette: usize,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            path: None,
+            range: 0..300,
+            delay: 50,
+            downscale: 1,
+            palette: 0,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// How big the frames are for a `width` x `height` grid.
+    pub fn frame_size(&self, width: u32, height: u32) -> (u32, u32) {
+        ((width / self.downscale).max(1), (height / self.downscale).max(1))
+    }
+}
+
+/// Runs the simulation without a window and writes generations `range` to `path` as a looping
+/// animation. Every cell is one of the palette's colours, so the palette is the whole colour table
+/// and nothing gets dithered.
+pub(crate) async fn export(config: &Config) -> anyhow::Result<()> {
+    let options = &config.export;
+    let path = options.path.as_ref().context("Nowhere to export to")?;
+    let Snapshot {
+        cells: state,
+        width,
+        height,
+        rule,
+        generation,
+        ..
+    } = config.start()?;
+    anyhow::ensure!(
+        options.range.start >= generation,
+        "Can't export from generation {}, the snapshot starts at {generation}",
+        options.range.start
+    );
+    info!("Rule: {}", rule);
+    let palette: Vec<u8> = ColorModes::with_mode(rule.species, options.palette)
+        .colors()
+        .into_iter()
+        .map(linear_to_srgb)
+        .collect();
+    let (frame_width, frame_height) = options.frame_size(width, height);
+    let frames = options.range.end - options.range.start;
+    let mut animation = Animation::create(
+        path,
+        frame_width,
+        frame_height,
+        &palette,
+        options.delay,
+        frames.try_into().context("Too many frames")?,
+    )?;
+    let mut metrics = match &config.metrics {
+        Some(path) => Some(MetricsWriter::create(path, rule.species)?),
+        None => None,
+    };
+    let mut sim = Simulation::with_state(state, width, height, rule).await?;
+    sim.set_generation(generation);
+    sim.set_kernel(config.kernel);
+    sim.set_count_changes(metrics.is_some());
+    info!(
+        "Exporting generations {}..{} to {}",
+        options.range.start,
+        options.range.end,
+        path.display()
+    );
+    let mut skip = options.range.start - generation;
+    while skip > 0 {
+        let ticks = skip.min(u32::MAX as u64);
+        sim.advance(ticks as u32);
+        skip -= ticks;
+    }
+    for frame in 0..frames {
+        if frame > 0 {
+            sim.advance(1);
+        }
+        let cells = sim.grid()?;
+        let indices = pick_cells(&cells, width, height, frame_width, frame_height);
+        animation.write(&indices)?;
+        if let Some(metrics) = &mut metrics {
+            metrics.write(&sim.stats()?)?;
+        }
+        if (frame + 1) % 100 == 0 {
+            info!("{}/{frames} frames", frame + 1);
+        }
+    }
+    animation.finish()?;
+    info!("Exported {frames} frames to {}", path.display());
+    Ok(())
+}
+
+/// One cell for every pixel of a smaller `frame_width` x `frame_height` frame, the same ones the
+/// shader would draw.
+fn pick_cells(
+    cells: &[u32],
+    width: u32,
+    height: u32,
+    frame_width: u32,
+    frame_height: u32,
+) -> Vec<u8> {
+    let mut indices = Vec::with_capacity((frame_width * frame_height) as usize);
+    for y in 0..frame_height {
+        // the middle of the pixel, like the fragment shader
+        let cell_y = ((2 * y + 1) as u64 * height as u64 / (2 * frame_height) as u64) as u32;
+        for x in 0..frame_width {
+            let cell_x = ((2 * x + 1) as u64 * width as u64 / (2 * frame_width) as u64) as u32;
+            // at most 16 species, so this always fits
+            indices.push(cells[(cell_y * width + cell_x) as usize] as u8);
+        }
+    }
+    indices
+}
+
+/// The palette is linear, but gifs and pngs are srgb.
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let c = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+enum Animation {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        width: u16,
+        height: u16,
+        /// In hundredths of a second, which is all gifs can do.
+        delay: u16,
+    },
+    Apng(png::Writer<BufWriter<File>>),
+}
+
+impl Animation {
+    fn create(
+        path: &std::path::Path,
+        width: u32,
+        height: u32,
+        palette: &[u8],
+        delay: u32,
+        frames: u32,
+    ) -> anyhow::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        // only once everything's checked, so a mistake doesn't leave an empty file behind
+        let create = || {
+            File::create(path)
+                .map(BufWriter::new)
+                .with_context(|| format!("Couldn't create {}", path.display()))
+        };
+        match extension.as_str() {
+            "gif" => {
+                let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+                    anyhow::bail!("Gifs can only be 65535 pixels wide or high, this would be {width}x{height}");
+                };
+                let delay = delay
+                    .div_ceil(10)
+                    .try_into()
+                    .context("Frame delay is too long")?;
+                let mut encoder = gif::Encoder::new(create()?, width, height, palette)
+                    .context("Couldn't write gif")?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .context("Couldn't write gif")?;
+                Ok(Self::Gif {
+                    encoder,
+                    width,
+                    height,
+                    delay,
+                })
+            }
+            "png" | "apng" => {
+                let delay = delay.try_into().context("Frame delay is too long")?;
+                let mut encoder = png::Encoder::new(create()?, width, height);
+                encoder.set_color(png::ColorType::Indexed);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_palette(palette.to_vec());
+                // 0 plays is forever
+                encoder
+                    .set_animated(frames, 0)
+                    .context("Couldn't write apng")?;
+                encoder
+                    .set_frame_delay(delay, 1000)
+                    .context("Couldn't write apng")?;
+                Ok(Self::Apng(
+                    encoder.write_header().context("Couldn't write apng")?,
+                ))
+            }
+            _ => anyhow::bail!(
+                "Don't know how to export {}, it should end in .gif, .png or .apng",
+                path.display()
+            ),
+        }
+    }
+
+    /// Adds a frame of palette indices.
+    fn write(&mut self, indices: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Self::Gif {
+                encoder,
+                width,
+                height,
+                delay,
+            } => {
+                let frame = gif::Frame {
+                    width: *width,
+                    height: *height,
+                    delay: *delay,
+                    buffer: Cow::Borrowed(indices),
+                    ..Default::default()
+                };
+                encoder.write_frame(&frame).context("Couldn't write gif")
+            }
+            Self::Apng(writer) => writer
+                .write_image_data(indices)
+                .context("Couldn't write apng"),
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Gif { encoder, .. } => {
+                let mut out = encoder.into_inner().context("Couldn't write gif")?;
+                std::io::Write::flush(&mut out).context("Couldn't write gif")
+            }
+            Self::Apng(writer) => writer.finish().context("Couldn't write apng"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_sizes() {
+        let options = |downscale| ExportOptions {
+            downscale,
+            ..ExportOptions::default()
+        };
+        assert_eq!(options(1).frame_size(480, 270), (480, 270));
+        assert_eq!(options(2).frame_size(480, 270), (240, 135));
+        // rounds down, and never all the way to nothing
+        assert_eq!(options(4).frame_size(481, 270), (120, 67));
+        assert_eq!(options(1000).frame_size(480, 270), (1, 1));
+    }
+
+    #[test]
+    fn picking_cells() {
+        let cells: Vec<u32> = (0..24).map(|i| i % 16).collect();
+        // full size is every cell as it is
+        let indices = pick_cells(&cells, 6, 4, 6, 4);
+        assert_eq!(indices, cells.iter().map(|&c| c as u8).collect::<Vec<_>>());
+        // half size takes the bottom right of every 2x2, where the middle of the pixel lands
+        assert_eq!(pick_cells(&cells, 6, 4, 3, 2), [7, 9, 11, 3, 5, 7]);
+        // a single pixel is the middle of the grid
+        assert_eq!(pick_cells(&cells, 6, 4, 1, 1), [cells[2 * 6 + 3] as u8]);
+        // a third of the way doesn't divide evenly, and still stays on the grid
+        let indices = pick_cells(&cells, 6, 4, 4, 3);
+        assert_eq!(indices.len(), 12);
+        assert_eq!(indices[11], cells[3 * 6 + 5] as u8);
+    }
+
+    #[test]
+    fn palettes() {
+        let palette = |mode| ColorModes::with_mode(3, mode).colors();
+        assert_eq!(palette(0), ColorModes::new(3).colors());
+        assert_eq!(palette(0), [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(palette(1), [0.0, 1.0, 1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 0.0]);
+        assert_eq!(palette(3), [0.0, 0.0, 0.0, 0.5, 0.5, 0.5, 1.0, 1.0, 1.0]);
+        assert_eq!(palette(crate::color::COLOR_MODES), palette(0));
+        assert_eq!(linear_to_srgb(0.0), 0);
+        assert_eq!(linear_to_srgb(0.5), 188);
+        assert_eq!(linear_to_srgb(1.0), 255);
+    }
+}
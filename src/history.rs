@@ -0,0 +1,65 @@
+use tracing::info;
+
+/// Most generations the history keeps, however small the grid is.
+const MAX_GENERATIONS: u64 = 4096;
+
+/// A ring of past generations kept on the gpu, so the game can step backwards.
+///
+/// Once it's full the oldest generation gets overwritten. Every generation is a slot in one big
+/// buffer, rather than a buffer each, so small grids don't turn into thousands of allocations.
+pub(crate) struct History {
+    buffer: wgpu::Buffer,
+    /// How big each slot is.
+    state_size: u64,
+    capacity: usize,
+    /// The slot the next generation goes in.
+    next: usize,
+    /// How many slots have a generation in them.
+    len: usize,
+}
+
+impl History {
+    /// Makes as many `state_size` byte slots as fit in `budget` bytes (at least one, and at most
+    /// [`MAX_GENERATIONS`]).
+    pub fn new(device: &wgpu::Device, state_size: u64, budget: u64) -> Self {
+        let capacity = (budget / state_size).clamp(1, MAX_GENERATIONS) as usize;
+        info!("Keeping {capacity} generations of history");
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("History Buffer"),
+            size: capacity as u64 * state_size,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            state_size,
+            capacity,
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Copies `state` into the ring.
+    pub fn push(&mut self, encoder: &mut wgpu::CommandEncoder, state: &wgpu::Buffer) {
+        let offset = self.next as u64 * self.state_size;
+        encoder.copy_buffer_to_buffer(state, 0, &self.buffer, offset, self.state_size);
+        self.next = (self.next + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+    }
+
+    /// Copies the newest generation into `state` and forgets it, false if there's nothing left.
+    pub fn pop(&mut self, encoder: &mut wgpu::CommandEncoder, state: &wgpu::Buffer) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+        self.next = (self.next + self.capacity - 1) % self.capacity;
+        self.len -= 1;
+        let offset = self.next as u64 * self.state_size;
+        encoder.copy_buffer_to_buffer(&self.buffer, offset, state, 0, self.state_size);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
@@ -0,0 +1,551 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use anyhow::Context;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::color::ColorModes;
+
+const DEFAULT_SEEDS: u32 = 64;
+const DEFAULT_STRIPE_WIDTH: u32 = 32;
+const DEFAULT_RING_WIDTH: u32 = 24;
+const DEFAULT_INVADERS: f64 = 0.001;
+const DEFAULT_SCALE: u32 = 96;
+/// A single cell can never win, so invaders start out as blobs this big.
+const INVADER_RADIUS: i32 = 3;
+
+/// How the grid gets filled in before the first generation.
+///
+/// Every generator only uses `seed` for its randomness, so the same seed always gives the same grid.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InitialState {
+    /// Every cell is a random species, picked with these weights (all the same if empty).
+    /// The original start.
+    Noise { weights: Vec<f64> },
+    /// Random points, each a random species, that every cell takes the species of the closest one of.
+    Voronoi { seeds: u32 },
+    /// Bands of each species in turn, `width` cells wide.
+    Stripes { vertical: bool, width: u32 },
+    /// Circles of each species in turn around the middle, `width` cells wide.
+    Rings { width: u32 },
+    /// All species 0, apart from sparse blobs of random other species.
+    /// `density` is the chance of any cell being the middle of one.
+    Invaders { density: f64 },
+    /// Smooth blobs from layered value noise, roughly `scale` cells across.
+    Smooth { scale: u32 },
+    /// A png or jpeg, stretched to the size of the grid.
+    Image {
+        path: PathBuf,
+        mapping: ImageMapping,
+    },
+}
+
+/// How the colour of a pixel turns into a species.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageMapping {
+    /// Whichever species' colour in the default palette is closest.
+    #[default]
+    Palette,
+    /// Red is species 0, green 1 and blue 2, whichever is brightest.
+    Channel,
+}
+
+impl Default for InitialState {
+    fn default() -> Self {
+        Self::Noise { weights: vec![] }
+    }
+}
+
+impl InitialState {
+    /// The next generator, with its default settings, for cycling through them.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Noise { .. } => Self::Voronoi {
+                seeds: DEFAULT_SEEDS,
+            },
+            Self::Voronoi { .. } => Self::Stripes {
+                vertical: true,
+                width: DEFAULT_STRIPE_WIDTH,
+            },
+            Self::Stripes { vertical: true, .. } => Self::Stripes {
+                vertical: false,
+                width: DEFAULT_STRIPE_WIDTH,
+            },
+            Self::Stripes {
+                vertical: false, ..
+            } => Self::Rings {
+                width: DEFAULT_RING_WIDTH,
+            },
+            Self::Rings { .. } => Self::Invaders {
+                density: DEFAULT_INVADERS,
+            },
+            Self::Invaders { .. } => Self::Smooth {
+                scale: DEFAULT_SCALE,
+            },
+            Self::Smooth { .. } | Self::Image { .. } => Self::default(),
+        }
+    }
+
+    pub fn check(&self, species: u32) -> anyhow::Result<()> {
+        match self {
+            Self::Noise { weights } if !weights.is_empty() => {
+                anyhow::ensure!(
+                    weights.len() == species as usize,
+                    "Need a weight for each of the {species} species, got {}",
+                    weights.len()
+                );
+                anyhow::ensure!(
+                    weights.iter().all(|w| w.is_finite() && *w >= 0.0)
+                        && weights.iter().sum::<f64>() > 0.0,
+                    "Weights can't be negative, and at least one has to be more than 0"
+                );
+            }
+            Self::Voronoi { seeds } => anyhow::ensure!(*seeds > 0, "Need at least one seed"),
+            Self::Stripes { width, .. } | Self::Rings { width } => {
+                anyhow::ensure!(*width > 0, "Width can't be 0")
+            }
+            Self::Invaders { density } => anyhow::ensure!(
+                (0.0..=1.0).contains(density),
+                "Density has to be between 0 and 1, got {density}"
+            ),
+            Self::Smooth { scale } => anyhow::ensure!(*scale > 0, "Scale can't be 0"),
+            Self::Noise { .. } | Self::Image { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Makes a row-major `width * height` grid of `species` species.
+    ///
+    /// Fails if the settings don't pass [`check`](Self::check) or the image can't be loaded.
+    pub fn generate(
+        &self,
+        width: u32,
+        height: u32,
+        species: u32,
+        seed: u64,
+    ) -> anyhow::Result<Vec<u32>> {
+        self.check(species)?;
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let (w, h) = (width as usize, height as usize);
+        let grid = match self.clone() {
+            Self::Noise { weights } if weights.is_empty() => {
+                (0..w * h).map(|_| rng.gen_range(0..species)).collect()
+            }
+            Self::Noise { weights } => {
+                let dist = WeightedIndex::new(&weights).expect("checked above");
+                (0..w * h).map(|_| dist.sample(&mut rng) as u32).collect()
+            }
+            Self::Voronoi { seeds } => {
+                let seeds: Vec<(f32, f32, u32)> = (0..seeds)
+                    .map(|_| {
+                        (
+                            rng.gen_range(0.0..width as f32),
+                            rng.gen_range(0.0..height as f32),
+                            rng.gen_range(0..species),
+                        )
+                    })
+                    .collect();
+                cells(width, height, |x, y| {
+                    let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+                    let closest = seeds.iter().min_by(|a, b| {
+                        distance_squared(a.0 - x, a.1 - y)
+                            .total_cmp(&distance_squared(b.0 - x, b.1 - y))
+                    });
+                    closest.expect("there's at least one seed").2
+                })
+            }
+            Self::Stripes {
+                vertical,
+                width: stripe,
+            } => {
+                // start on a random species so different seeds still look different
+                let first = rng.gen_range(0..species);
+                cells(width, height, |x, y| {
+                    let along = if vertical { x } else { y };
+                    (along / stripe + first) % species
+                })
+            }
+            Self::Rings { width: ring } => {
+                let first = rng.gen_range(0..species);
+                let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+                cells(width, height, |x, y| {
+                    let d = distance_squared(x as f32 + 0.5 - cx, y as f32 + 0.5 - cy).sqrt();
+                    (d as u32 / ring + first) % species
+                })
+            }
+            Self::Invaders { density } => {
+                let mut grid = vec![0; w * h];
+                for i in 0..w * h {
+                    if !rng.gen_bool(density) {
+                        continue;
+                    }
+                    let invader = rng.gen_range(1..species);
+                    let (x, y) = ((i % w) as i32, (i / w) as i32);
+                    let r = INVADER_RADIUS;
+                    for (dx, dy) in (-r..=r).flat_map(|dy| (-r..=r).map(move |dx| (dx, dy))) {
+                        let (bx, by) = (x + dx, y + dy);
+                        if dx * dx + dy * dy <= r * r
+                            && (0..w as i32).contains(&bx)
+                            && (0..h as i32).contains(&by)
+                        {
+                            grid[by as usize * w + bx as usize] = invader;
+                        }
+                    }
+                }
+                grid
+            }
+            Self::Smooth { scale } => {
+                let noise = smooth_noise(width, height, scale, &mut rng);
+                // split at the quantiles so every species gets the same share, the noise bunches up in the middle
+                let mut sorted = noise.clone();
+                sorted.sort_by(f32::total_cmp);
+                let cuts: Vec<f32> = (1..species)
+                    .map(|s| sorted[sorted.len() * s as usize / species as usize])
+                    .collect();
+                noise
+                    .iter()
+                    .map(|v| cuts.iter().filter(|&&cut| *v >= cut).count() as u32)
+                    .collect()
+            }
+            Self::Image { path, mapping } => load_image(&path, mapping, width, height, species)?,
+        };
+        Ok(grid)
+    }
+}
+
+fn load_image(
+    path: &std::path::Path,
+    mapping: ImageMapping,
+    width: u32,
+    height: u32,
+    species: u32,
+) -> anyhow::Result<Vec<u32>> {
+    let image = image::open(path).with_context(|| format!("Couldn't load {}", path.display()))?;
+    let image = image
+        .resize_exact(width, height, image::imageops::FilterType::Triangle)
+        .into_rgba32f();
+    let palette = ColorModes::new(species).colors();
+    let grid = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            // see-through bits count as black, and the palette is linear but images are srgb
+            let rgb = [r, g, b].map(|c| srgb_to_linear(c) * a);
+            match mapping {
+                ImageMapping::Palette => {
+                    let distance = |color: &[f32]| -> f32 {
+                        color.iter().zip(&rgb).map(|(c, p)| (c - p) * (c - p)).sum()
+                    };
+                    let closest = palette
+                        .chunks(3)
+                        .enumerate()
+                        .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)));
+                    closest.map_or(0, |(species, _)| species as u32)
+                }
+                ImageMapping::Channel => {
+                    // ties go to the first channel, like everywhere else
+                    let mut brightest = 0;
+                    for channel in 1..3 {
+                        if rgb[channel] > rgb[brightest] {
+                            brightest = channel;
+                        }
+                    }
+                    brightest as u32 % species
+                }
+            }
+        })
+        .collect();
+    Ok(grid)
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// not hypot, that isn't the same on every platform, and seeds have to be
+fn distance_squared(dx: f32, dy: f32) -> f32 {
+    dx * dx + dy * dy
+}
+
+fn cells(width: u32, height: u32, f: impl Fn(u32, u32) -> u32) -> Vec<u32> {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| f(x, y))
+        .collect()
+}
+
+/// A few octaves of random values on a lattice, smoothly blended between.
+fn smooth_noise(width: u32, height: u32, scale: u32, rng: &mut ChaCha8Rng) -> Vec<f32> {
+    let mut noise = vec![0.0; width as usize * height as usize];
+    let mut scale = scale as f32;
+    let mut amplitude = 1.0;
+    for _ in 0..3 {
+        let lattice_w = (width as f32 / scale) as usize + 2;
+        let lattice_h = (height as f32 / scale) as usize + 2;
+        let lattice: Vec<f32> = (0..lattice_w * lattice_h).map(|_| rng.gen()).collect();
+        for y in 0..height {
+            for x in 0..width {
+                let (fx, fy) = (x as f32 / scale, y as f32 / scale);
+                let (ix, iy) = (fx as usize, fy as usize);
+                // smoothstep so the lattice doesn't show
+                let (tx, ty) = (smoothstep(fx.fract()), smoothstep(fy.fract()));
+                let at = |dx: usize, dy: usize| lattice[(iy + dy) * lattice_w + ix + dx];
+                let top = at(0, 0) + (at(1, 0) - at(0, 0)) * tx;
+                let bottom = at(0, 1) + (at(1, 1) - at(0, 1)) * tx;
+                noise[(y * width + x) as usize] += (top + (bottom - top) * ty) * amplitude;
+            }
+        }
+        scale = (scale / 2.0).max(1.0);
+        amplitude /= 2.0;
+    }
+    noise
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl fmt::Display for InitialState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Noise { weights } if weights.is_empty() => write!(f, "noise"),
+            Self::Noise { weights } => {
+                let weights: Vec<String> = weights.iter().map(|w| w.to_string()).collect();
+                write!(f, "noise:{}", weights.join(","))
+            }
+            Self::Voronoi { seeds } => write!(f, "voronoi:{seeds}"),
+            Self::Stripes {
+                vertical: true,
+                width,
+            } => write!(f, "vstripes:{width}"),
+            Self::Stripes {
+                vertical: false,
+                width,
+            } => write!(f, "hstripes:{width}"),
+            Self::Rings { width } => write!(f, "rings:{width}"),
+            Self::Invaders { density } => write!(f, "invaders:{density}"),
+            Self::Smooth { scale } => write!(f, "smooth:{scale}"),
+            Self::Image {
+                path,
+                mapping: ImageMapping::Palette,
+            } => write!(f, "image:{}", path.display()),
+            Self::Image {
+                path,
+                mapping: ImageMapping::Channel,
+            } => write!(f, "image-channel:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for InitialState {
+    type Err = anyhow::Error;
+
+    /// Parses `noise[:w,w,..]`, `voronoi[:seeds]`, `vstripes[:width]`, `hstripes[:width]`,
+    /// `rings[:width]`, `invaders[:density]`, `smooth[:scale]`, `image:<path>` or `image-channel:<path>`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (s, None),
+        };
+        fn parse_or<T: FromStr>(value: Option<&str>, default: T) -> anyhow::Result<T>
+        where
+            T::Err: std::error::Error + Send + Sync + 'static,
+        {
+            match value {
+                Some(value) => value.parse().with_context(|| format!("Bad value: {value}")),
+                None => Ok(default),
+            }
+        }
+        let state = match name {
+            "noise" => {
+                let weights = match value {
+                    Some(value) => value
+                        .split(',')
+                        .map(|w| w.trim().parse().with_context(|| format!("Bad weight: {w}")))
+                        .collect::<anyhow::Result<_>>()?,
+                    None => vec![],
+                };
+                Self::Noise { weights }
+            }
+            "voronoi" => Self::Voronoi {
+                seeds: parse_or(value, DEFAULT_SEEDS)?,
+            },
+            "vstripes" | "hstripes" => Self::Stripes {
+                vertical: name == "vstripes",
+                width: parse_or(value, DEFAULT_STRIPE_WIDTH)?,
+            },
+            "rings" => Self::Rings {
+                width: parse_or(value, DEFAULT_RING_WIDTH)?,
+            },
+            "invaders" => Self::Invaders {
+                density: parse_or(value, DEFAULT_INVADERS)?,
+            },
+            "smooth" => Self::Smooth {
+                scale: parse_or(value, DEFAULT_SCALE)?,
+            },
+            "image" | "image-channel" => Self::Image {
+                path: value
+                    .context("image needs a path, like image:logo.png")?
+                    .into(),
+                mapping: if name == "image" {
+                    ImageMapping::Palette
+                } else {
+                    ImageMapping::Channel
+                },
+            },
+            _ => anyhow::bail!("Unknown initial state: {s}"),
+        };
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_generator() -> Vec<InitialState> {
+        let mut all = vec![InitialState::default()];
+        while all.last().unwrap().next() != InitialState::default() {
+            all.push(all.last().unwrap().next());
+        }
+        all.push(InitialState::Noise {
+            weights: vec![1.0, 0.0, 2.5],
+        });
+        all
+    }
+
+    #[test]
+    fn parsing_round_trips() {
+        for state in every_generator() {
+            assert_eq!(state.to_string().parse::<InitialState>().unwrap(), state);
+        }
+        let image = InitialState::Image {
+            path: "logo.png".into(),
+            mapping: ImageMapping::Channel,
+        };
+        assert_eq!(image.to_string(), "image-channel:logo.png");
+        assert_eq!(image.to_string().parse::<InitialState>().unwrap(), image);
+        // paths can have colons in them
+        assert_eq!(
+            "image:C:/logo.png".parse::<InitialState>().unwrap(),
+            InitialState::Image {
+                path: "C:/logo.png".into(),
+                mapping: ImageMapping::Palette,
+            }
+        );
+    }
+
+    #[test]
+    fn parsing_defaults() {
+        assert_eq!("voronoi".parse::<InitialState>().unwrap(), InitialState::Voronoi { seeds: DEFAULT_SEEDS });
+        assert_eq!(
+            "hstripes".parse::<InitialState>().unwrap(),
+            InitialState::Stripes {
+                vertical: false,
+                width: DEFAULT_STRIPE_WIDTH
+            }
+        );
+        assert_eq!("invaders:0.5".parse::<InitialState>().unwrap(), InitialState::Invaders { density: 0.5 });
+        for bad in ["", "static", "voronoi:x", "rings:-1", "noise:1,x", "image", "smooth:1.5"] {
+            assert!(bad.parse::<InitialState>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn checks() {
+        let bad = [
+            ("noise:1,2", 3),
+            ("noise:1,-1,1", 3),
+            ("noise:0,0,0", 3),
+            ("voronoi:0", 3),
+            ("vstripes:0", 3),
+            ("rings:0", 3),
+            ("invaders:1.5", 3),
+            ("smooth:0", 3),
+        ];
+        for (state, species) in bad {
+            let state: InitialState = state.parse().unwrap();
+            assert!(state.check(species).is_err(), "{state}");
+            assert!(state.generate(8, 8, species, 0).is_err(), "{state}");
+        }
+        assert!("noise:1,2".parse::<InitialState>().unwrap().check(2).is_ok());
+    }
+
+    #[test]
+    fn seeds_are_reproducible() {
+        for state in every_generator() {
+            for species in [2, 3, 7] {
+                let state = match state {
+                    // one weight per species
+                    InitialState::Noise { ref weights } if !weights.is_empty() => InitialState::Noise {
+                        weights: (0..species).map(|s| s as f64).collect(),
+                    },
+                    ref state => state.clone(),
+                };
+                let grid = state.generate(37, 23, species, 42).unwrap();
+                assert_eq!(grid.len(), 37 * 23);
+                assert!(grid.iter().all(|&cell| cell < species), "{state}");
+                assert_eq!(state.generate(37, 23, species, 42).unwrap(), grid, "{state}");
+            }
+        }
+        // the default start is plain noise, and a different seed gives a different grid
+        let noise = InitialState::default();
+        assert_ne!(noise.generate(37, 23, 3, 42).unwrap(), noise.generate(37, 23, 3, 43).unwrap());
+        // pinned, so a change to the rng or how it's used shows up here rather than in someone's old seeds
+        assert_eq!(noise.generate(8, 1, 3, 42).unwrap(), [0, 2, 0, 2, 1, 1, 2, 0]);
+    }
+
+    #[test]
+    fn generators() {
+        let stripes = InitialState::Stripes {
+            vertical: true,
+            width: 2,
+        };
+        let grid = stripes.generate(6, 2, 3, 0).unwrap();
+        let first = grid[0];
+        let row: Vec<u32> = [0, 0, 1, 1, 2, 2].iter().map(|s| (s + first) % 3).collect();
+        assert_eq!(grid, [row.clone(), row].concat());
+        let grid = InitialState::Invaders { density: 0.0 }.generate(10, 10, 3, 0).unwrap();
+        assert!(grid.iter().all(|&cell| cell == 0));
+        let grid = InitialState::Noise {
+            weights: vec![0.0, 1.0, 0.0],
+        }
+        .generate(10, 10, 3, 0)
+        .unwrap();
+        assert!(grid.iter().all(|&cell| cell == 1));
+        // smooth noise is split evenly between the species
+        let grid = InitialState::Smooth { scale: 8 }.generate(64, 64, 4, 0).unwrap();
+        for species in 0..4 {
+            let count = grid.iter().filter(|&&cell| cell == species).count();
+            assert!((1000..=1050).contains(&count), "{count} of species {species}");
+        }
+    }
+
+    #[test]
+    fn images() {
+        let path = std::env::temp_dir().join(format!("ow_my_eyes-test-{}.png", std::process::id()));
+        // red, green, blue and nearly black
+        let pixels = [255, 0, 0, 0, 255, 0, 0, 0, 255, 10, 10, 10];
+        image::save_buffer(&path, &pixels, 4, 1, image::ExtendedColorType::Rgb8).unwrap();
+        let palette = InitialState::Image {
+            path: path.clone(),
+            mapping: ImageMapping::Palette,
+        };
+        let channel = InitialState::Image {
+            path: path.clone(),
+            mapping: ImageMapping::Channel,
+        };
+        let by_palette = palette.generate(4, 1, 3, 0);
+        let by_channel = channel.generate(4, 1, 3, 0);
+        std::fs::remove_file(&path).unwrap();
+        // the default palette is red, green and blue for 3 species
+        assert_eq!(&by_palette.unwrap()[..3], [0, 1, 2]);
+        // the grey pixel ties, which goes to red
+        assert_eq!(by_channel.unwrap(), [0, 1, 2, 0]);
+        assert!(palette.generate(4, 1, 3, 0).is_err(), "the image is gone");
+    }
+}
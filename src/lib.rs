@@ -1,10 +1,39 @@
 #![forbid(unsafe_code)]
 
+mod batch;
+mod capture;
+mod color;
+mod config;
+mod cpu;
+mod export;
+mod history;
+mod initial;
+mod metrics;
+mod packing;
+mod record;
+mod rule;
+mod simulation;
+mod snapshot;
+mod stats;
+mod view;
+pub use config::{DEFAULT_HEIGHT, DEFAULT_WIDTH};
+pub use cpu::{step, step_with_rule};
+pub use initial::{ImageMapping, InitialState};
+pub use packing::CellLayout;
+pub use rule::{Boundary, Dominance, Neighbourhood, Rule, Takeover, MAX_RADIUS, MAX_SCORE, MAX_SPECIES};
+pub use simulation::{Kernel, Simulation};
+pub use snapshot::Snapshot;
+pub use stats::Stats;
+
 use anyhow::Context;
-use rand::Rng;
+use capture::PendingFrame;
+use color::ColorModes;
+use simulation::GpuSimulation;
+use config::Config;
+use metrics::MetricsWriter;
+use record::{RecordOptions, Recorder};
 use std::{num::NonZeroU64, sync::Arc};
-const WIDTH: u32 = 1920;
-const HEIGHT: u32 = 1080;
+use view::{AgeMode, ScaleMode, View};
 use game_loop::{game_loop, TimeTrait};
 use tracing::{error, info, instrument};
 use wgpu::{
@@ -16,18 +45,20 @@ use winit::{
     event::{Event, WindowEvent}, keyboard::NamedKey, window::Fullscreen
 };
 
-fn create_initial_state() -> Vec<u32> {
-    let mut rng = rand::thread_rng();
-    (0..WIDTH * HEIGHT)
-        .map(|_| rng.gen_range(0..=2u32))
-        .collect()
-}
-
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 
 //const FPS: usize = 60;
+/// Generations per update that +/- step through, there's 60 updates a second.
+/// Under 1 skips updates, over 1 runs that many ticks per update in one go.
+const SPEEDS: [f64; 10] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0];
+/// Index into `SPEEDS` to start at, one generation per update.
+const DEFAULT_SPEED: usize = 3;
+/// How much gpu memory to spend on past generations for stepping backwards.
+const HISTORY_BYTES: u64 = 64 << 20;
+/// Where F5 saves to and F9 loads from.
+const SNAPSHOT_PATH: &str = "ow_my_eyes.snapshot";
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
@@ -52,19 +83,32 @@ pub async fn run() {
         tracing_wasm::set_as_global_default();
         std::panic::set_hook(Box::new(console_error_panic_hook::hook));
     }
-    if let Err(e) = render_to_window().await {
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Error: {:?}", e);
+            return;
+        }
+    };
+    let result = if config.export.path.is_some() {
+        export::export(&config).await
+    } else if config.batch.headless {
+        batch::run(&config).await
+    } else {
+        render_to_window(config).await
+    };
+    if let Err(e) = result {
         error!("Error: {:?}", e);
     }
 }
 
-async fn render_to_window() -> anyhow::Result<()> {
+async fn render_to_window(config: Config) -> anyhow::Result<()> {
     let event_loop = winit::event_loop::EventLoop::new().context("Failed to create event loop")?;
     info!("Creating window");
     let window = winit::window::WindowBuilder::new()
         .with_title("ow my eyes")
-        .with_inner_size(winit::dpi::PhysicalSize::new(WIDTH, HEIGHT))
+        .with_inner_size(winit::dpi::PhysicalSize::new(config.width, config.height))
         .with_fullscreen(Some(Fullscreen::Borderless(None)))
-        .with_resizable(false)
         .build(&event_loop)
         .context("Failed to create window")?;
     #[cfg(target_arch = "wasm32")]
@@ -93,7 +137,7 @@ async fn render_to_window() -> anyhow::Result<()> {
             .context("Failed to create surface!")?;
     // let surface_caps = surface.get_capabilities(&game.adapter);
     info!("Creating game");
-    let game = Game::new(surface, instance).await?;
+    let game = Game::new(surface, instance, &config, window.inner_size()).await?;
     //let winit_game = WinitGame { game, surface };
     info!("Starting game loop");
     game_loop(
@@ -109,7 +153,10 @@ async fn render_to_window() -> anyhow::Result<()> {
             if u.number_of_renders() % 60 == 0 {
                 // round to no decimal places
                 let fps = (1.0 / between).round() as u64;
-                info!("FPS: {}", fps);
+                match &u.game.stats {
+                    Some(stats) => info!("FPS: {} | {}", fps, stats),
+                    None => info!("FPS: {}", fps),
+                }
             }
         },
         |r| {
@@ -131,6 +178,13 @@ async fn render_to_window() -> anyhow::Result<()> {
             {
                 h.exit();
             }
+            if let Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } = e
+            {
+                h.game.resize(*size);
+            }
             if let Event::WindowEvent { event: WindowEvent::KeyboardInput { event, .. }, .. } = e {
                 if event.state.is_pressed() {
                     if event.logical_key == "p" {
@@ -141,10 +195,41 @@ async fn render_to_window() -> anyhow::Result<()> {
                         h.game.color_manager.next();
                     } else if event.logical_key == NamedKey::ArrowLeft {
                         h.game.color_manager.prev();
+                    } else if event.logical_key == "s" {
+                        h.game.next_scale_mode();
+                    } else if event.logical_key == "h" {
+                        h.game.next_age_mode();
                     } else if event.logical_key == "r" {
-                        h.game.queue
-                            .write_buffer(&h.game.data_buffer, 0, 
-                            bytemuck::cast_slice(&create_initial_state()));
+                        h.game.reset();
+                    } else if event.logical_key == "i" {
+                        h.game.next_initial_state();
+                    } else if event.logical_key == "k" {
+                        h.game.next_kernel();
+                    } else if event.logical_key == "+" || event.logical_key == "=" {
+                        // = is + without shift on most keyboards
+                        h.game.set_speed(h.game.speed + 1);
+                    } else if event.logical_key == "-" {
+                        h.game.set_speed(h.game.speed.saturating_sub(1));
+                    } else if event.logical_key == NamedKey::Space {
+                        h.game.toggle_pause();
+                    } else if event.logical_key == "." {
+                        h.game.step_forward();
+                    } else if event.logical_key == "," {
+                        h.game.step_back();
+                    } else if event.logical_key == NamedKey::F5 {
+                        match h.game.save_snapshot(SNAPSHOT_PATH) {
+                            Ok(()) => info!("Saved snapshot to {SNAPSHOT_PATH}"),
+                            Err(e) => error!("Couldn't save snapshot: {:?}", e),
+                        }
+                    } else if event.logical_key == NamedKey::F12 {
+                        h.game.screenshot();
+                    } else if event.logical_key == "v" {
+                        h.game.toggle_recording();
+                    } else if event.logical_key == NamedKey::F9 {
+                        match h.game.load_snapshot(SNAPSHOT_PATH) {
+                            Ok(()) => info!("Loaded snapshot from {SNAPSHOT_PATH}"),
+                            Err(e) => error!("Couldn't load snapshot: {:?}", e),
+                        }
                     }
                 }
                 
@@ -158,26 +243,50 @@ async fn render_to_window() -> anyhow::Result<()> {
 }
 
 struct Game {
-    device: wgpu::Device,
-    //adapter: wgpu::Adapter,
-    queue: wgpu::Queue,
-    /*texture: wgpu::Texture,
-    texture_view: wgpu::TextureView,*/
-    //out_buffer: wgpu::Buffer,
-    data_buffer: wgpu::Buffer,
-    data_buffer_copy: wgpu::Buffer,
+    sim: GpuSimulation,
+    /// Kept around for building a new simulation when a snapshot gets loaded.
+    shader: wgpu::ShaderModule,
     render_pipeline: wgpu::RenderPipeline,
-    compute_pipeline: wgpu::ComputePipeline,
-    compute_bind_group_layout: wgpu::BindGroupLayout,
     render_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_groups: [wgpu::BindGroup; 2],
     color_manager: ColorModes,
     color_buffer: wgpu::Buffer,
+    view_buffer: wgpu::Buffer,
+    scale_mode: ScaleMode,
+    age_mode: AgeMode,
     surface: wgpu::Surface<'static>,
+    surface_config: wgpu::SurfaceConfiguration,
+    rule: Rule,
+    /// Index into `SPEEDS`.
+    speed: usize,
+    /// Fractions of a tick left over from slow speeds.
+    pending_ticks: f64,
+    paused: bool,
+    /// What the grid got (and gets reset to) seeded with.
+    seed: u64,
+    init: InitialState,
+    /// A screenshot on its way back from the gpu, and the file it's going to.
+    pending_screenshot: Option<(String, PendingFrame)>,
+    record_options: RecordOptions,
+    recorder: Option<Recorder>,
+    /// The latest population count back from the gpu.
+    stats: Option<Stats>,
+    /// Count every this many generations.
+    stats_every: u64,
+    /// The generation the last count was asked for at, `None` to count again as soon as possible.
+    stats_generation: Option<u64>,
+    /// Where every count gets written, if anywhere.
+    metrics: Option<MetricsWriter>,
 }
 
 impl Game {
     #[instrument(skip_all)]
-    async fn new(surface: Surface<'static>, instance: Instance) -> anyhow::Result<Self> {
+    async fn new(
+        surface: Surface<'static>,
+        instance: Instance,
+        game_config: &Config,
+        window_size: winit::dpi::PhysicalSize<u32>,
+    ) -> anyhow::Result<Self> {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
@@ -187,17 +296,7 @@ impl Game {
             .await
             .context("No adapter found!")?;
         info!("Adapter: {:?}", adapter.get_info());
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::downlevel_defaults(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .context("No device found!")?;
+        let (device, queue) = simulation::request_device(&adapter).await?;
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = *surface_caps
             .formats
@@ -207,8 +306,9 @@ impl Game {
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
-            width: WIDTH,
-            height: HEIGHT,
+            // the window might not be open yet on some platforms, so don't configure a 0x0 surface
+            width: window_size.width.max(1),
+            height: window_size.height.max(1),
             present_mode: wgpu::PresentMode::AutoVsync,
             desired_maximum_frame_latency: 2,
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
@@ -217,7 +317,7 @@ impl Game {
         surface.configure(&device, &config);
 
         info!("Creating color buffer");
-        let color_manager = ColorModes::new();
+        let color_manager = ColorModes::new(game_config.rule.species);
         let colors = color_manager.colors();
         let color_buffer_desc = wgpu::util::BufferInitDescriptor {
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
@@ -226,25 +326,15 @@ impl Game {
         };
         let color_buffer = device.create_buffer_init(&color_buffer_desc);
 
-        info!("Creating data buffer");
-        let init_state = create_initial_state();
-        //let data_buffer_size = (WIDTH * HEIGHT) as wgpu::BufferAddress;
-        let data_buffer_desc = wgpu::util::BufferInitDescriptor {
-            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            label: Some("Data Buffer"),
-            contents: bytemuck::cast_slice(&init_state),
-        };
-        let data_buffer = device.create_buffer_init(&data_buffer_desc);
-        // create data buffer copy
-        info!("Creating data buffer copy");
-        let data_buffer_desc_copy = wgpu::util::BufferInitDescriptor {
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-            label: Some("Data Buffer Copy"),
-            contents: &(0..WIDTH * HEIGHT * std::mem::size_of::<u32>() as u32)
-                .map(|_| 0)
-                .collect::<Vec<_>>(),
-        };
-        let data_buffer_copy = device.create_buffer_init(&data_buffer_desc_copy);
+        info!("Creating view buffer");
+        let scale_mode = ScaleMode::default();
+        let view = scale_mode.view((game_config.width, game_config.height), (config.width, config.height));
+        let view_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            label: Some("View Buffer"),
+            contents: bytemuck::bytes_of(&view),
+        });
+
         info!("Compiling Shader");
         let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
         info!("Creating render pipeline");
@@ -258,7 +348,7 @@ impl Game {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             has_dynamic_offset: false,
-                            min_binding_size: Some(NonZeroU64::new(16).unwrap()),
+                            min_binding_size: Some(NonZeroU64::new(4).unwrap()),
                             ty: wgpu::BufferBindingType::Storage { read_only: false },
                         },
                     },
@@ -272,6 +362,37 @@ impl Game {
                             ty: wgpu::BufferBindingType::Storage { read_only: true },
                         },
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        count: None,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Uniform,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        count: None,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Uniform,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        count: None,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            // only read, but the shader declares it read_write for the compute kernels
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        },
+                    },
                 ],
             });
 
@@ -320,438 +441,542 @@ impl Game {
             },
             multiview: None, // 5.
         });
-        info!("Creating compute pipeline");
-        let compute_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        count: None,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(NonZeroU64::new(16).unwrap()),
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        },
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        count: None,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(NonZeroU64::new(16).unwrap()),
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        },
-                    },
-                ],
-            });
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Compute Pipeline Layout"),
-            bind_group_layouts: &[&compute_bind_group_layout],
-            push_constant_ranges: &[],
-        });
-
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Compute Pipeline"),
-            layout: Some(&pipeline_layout),
-            module: &shader,
-            entry_point: "compute",
-        });
-        Ok(Self {
-            device,
-            queue,
-            //adapter,
-            data_buffer,
+        let init_state = game_config.init.generate(
+            game_config.width,
+            game_config.height,
+            game_config.rule.species,
+            game_config.seed,
+        )?;
+        info!("Rule: {}", game_config.rule);
+        info!("Initial state: {}", game_config.init);
+        info!("Seed: {}", game_config.seed);
+        let mut sim = GpuSimulation::new(
+            Arc::new(device),
+            Arc::new(queue),
+            &shader,
+            &init_state,
+            game_config.width,
+            game_config.height,
+            &game_config.rule,
+        )?;
+        sim.kernel = game_config.kernel;
+        // for the changed cells in the stats, they're only read back along with them
+        sim.count_changes = true;
+        info!("Kernel: {}", sim.kernel);
+        sim.enable_history(HISTORY_BYTES);
+        let render_bind_groups =
+            create_render_bind_groups(&sim, &render_bind_group_layout, &color_buffer, &view_buffer);
+        let mut game = Self {
+            sim,
+            shader,
             render_pipeline,
-            compute_pipeline,
-            data_buffer_copy,
-            compute_bind_group_layout,
             render_bind_group_layout,
+            render_bind_groups,
             surface,
             color_manager,
             color_buffer,
-        })
+            view_buffer,
+            scale_mode,
+            age_mode: AgeMode::default(),
+            surface_config: config,
+            rule: game_config.rule.clone(),
+            speed: DEFAULT_SPEED,
+            pending_ticks: 0.0,
+            paused: false,
+            seed: game_config.seed,
+            init: game_config.init.clone(),
+            pending_screenshot: None,
+            record_options: game_config.record,
+            recorder: None,
+            stats: None,
+            stats_every: game_config.stats_every,
+            stats_generation: None,
+            metrics: None,
+        };
+        if let Some(path) = &game_config.load {
+            game.load_snapshot(path)?;
+            info!("Loaded snapshot from {}", path.display());
+        }
+        // after loading, that might have changed the species count
+        if let Some(path) = &game_config.metrics {
+            game.metrics = Some(MetricsWriter::create(path, game.rule.species)?);
+            info!("Writing metrics to {}", path.display());
+        }
+        Ok(game)
+    }
+    fn save_snapshot(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let snapshot = Snapshot {
+            width: self.sim.width,
+            height: self.sim.height,
+            rule: self.rule.clone(),
+            seed: self.seed,
+            init: self.init.clone(),
+            generation: self.sim.generation,
+            cells: self.sim.layout.unpack(&self.sim.read_back()?),
+        };
+        snapshot.save(path)
+    }
+    /// Picks up where a snapshot left off, the size and rule can be different to what's running now.
+    fn load_snapshot(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let snapshot = Snapshot::load(path)?;
+        // the frames would change size partway through
+        self.stop_recording();
+        let mut sim = GpuSimulation::new(
+            self.sim.device.clone(),
+            self.sim.queue.clone(),
+            &self.shader,
+            &snapshot.cells,
+            snapshot.width,
+            snapshot.height,
+            &snapshot.rule,
+        )?;
+        sim.generation = snapshot.generation;
+        sim.kernel = self.sim.kernel;
+        sim.count_changes = self.sim.count_changes;
+        sim.enable_history(HISTORY_BYTES);
+        if let Err(e) = sim.set_track_ages(self.age_mode != AgeMode::Off) {
+            error!("Couldn't track ages: {:?}", e);
+            self.age_mode = AgeMode::Off;
+        }
+        if snapshot.rule.species != self.rule.species {
+            self.color_manager = ColorModes::new(snapshot.rule.species);
+        }
+        // the old one might be the wrong size for the new species count
+        self.color_buffer = sim.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            label: Some("Color Buffer"),
+            contents: bytemuck::cast_slice(&self.color_manager.colors()),
+        });
+        self.render_bind_groups = create_render_bind_groups(
+            &sim,
+            &self.render_bind_group_layout,
+            &self.color_buffer,
+            &self.view_buffer,
+        );
+        self.sim = sim;
+        self.stats_generation = None;
+        self.rule = snapshot.rule;
+        self.seed = snapshot.seed;
+        self.init = snapshot.init;
+        self.update_view();
+        info!("Rule: {}", self.rule);
+        info!("Generation {}", self.sim.generation);
+        Ok(())
     }
     fn tick(&mut self) {
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Command Encoder"),
-            });
-        encoder.copy_buffer_to_buffer(
-            &self.data_buffer,
-            0,
-            &self.data_buffer_copy,
-            0,
-            (WIDTH * HEIGHT * std::mem::size_of::<u32>() as u32) as wgpu::BufferAddress,
-        );
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self.compute_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: self.data_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: self.data_buffer_copy.as_entire_binding(),
-                },
-            ],
-        });
-        {
-            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Compute"),
-                timestamp_writes: None,
-            });
-            cpass.set_bind_group(0, &bind_group, &[]);
-            cpass.set_pipeline(&self.compute_pipeline);
-            cpass.dispatch_workgroups(120, 120, 1); // 120x120x1 workgroups with 16x9x1 threads
-        }
-
-        let ind = self.queue.submit(Some(encoder.finish()));
+        self.finish_screenshot();
         self.color_manager.tick();
-        self.device
-            .poll(wgpu::MaintainBase::WaitForSubmissionIndex(ind));
+        if !self.paused {
+            self.pending_ticks += SPEEDS[self.speed];
+            let ticks = self.pending_ticks.floor();
+            self.pending_ticks -= ticks;
+            self.advance(ticks as u64);
+        }
+        self.update_stats();
+    }
+    /// Picks up the last count if it's back, and starts another one if it's due.
+    /// With `--metrics` it counts right away and waits for it instead, so no generation gets missed.
+    fn update_stats(&mut self) {
+        let generation = self.sim.generation;
+        let due = self
+            .stats_generation
+            .is_none_or(|last| last.abs_diff(generation) >= self.stats_every);
+        if self.metrics.is_some() {
+            // every count has to make it into the file, so wait for it rather than skip any
+            if due {
+                self.stats_generation = Some(generation);
+                match self.sim.stats() {
+                    Ok(stats) => self.got_stats(stats),
+                    Err(e) => error!("Couldn't count species: {:?}", e),
+                }
+            }
+            return;
+        }
+        match self.sim.poll_stats() {
+            Some(Ok(stats)) => self.got_stats(stats),
+            Some(Err(e)) => error!("Couldn't count species: {:?}", e),
+            None => {}
+        }
+        if due && self.sim.request_stats() {
+            self.stats_generation = Some(generation);
+        }
     }
-    fn render(&mut self) -> anyhow::Result<()> {
-        let output = self.surface.get_current_texture()?;
-        let texture_view = output.texture.create_view(&wgpu::TextureViewDescriptor {
-            ..Default::default()
-        });
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Command Encoder"),
-            });
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self.render_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: self.data_buffer.as_entire_binding(),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: self.color_buffer.as_entire_binding(),
-            }],
-        });
-        {
-            let render_pass_desc = wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
+    fn got_stats(&mut self, stats: Stats) {
+        if let Some(metrics) = &mut self.metrics {
+            if let Err(e) = metrics.write(&stats) {
+                error!("Stopped writing metrics: {:?}", e);
+                self.metrics = None;
+            }
+        }
+        self.stats = Some(stats);
+    }
+    /// Runs `ticks` generations, stopping along the way at any the recording wants.
+    /// With `--metrics`, it stops at every generation that gets counted too.
+    fn advance(&mut self, mut ticks: u64) {
+        while ticks > 0 {
+            if self.metrics.is_some() {
+                self.update_stats();
+            }
+            let step = match &self.recorder {
+                Some(recorder) => ticks.min(
+                    recorder
+                        .next_generation
+                        .saturating_sub(self.sim.generation)
+                        .max(1),
+                ),
+                None => ticks,
             };
-            let mut render_pass = encoder.begin_render_pass(&render_pass_desc);
-
-            render_pass.set_pipeline(&self.render_pipeline);
-
-            render_pass.set_bind_group(0, &bind_group, &[]);
-            render_pass.draw(0..3, 0..1);
-        };
-        self.color_manager.push_to_gpu(&mut self.queue, &self.color_buffer);
-        self.queue.submit(Some(encoder.finish()));
-        //self.device.poll(wgpu::Maintain::WaitForSubmissionIndex(id));
-        output.present();
-        Ok(())
-    }
-}
-
-
-
-
-struct ColorModes {
-    mode: usize,
-    colors: Box<[[Color; 3]]>,
-    blender: ColorBlender,
-    blender_enabled: bool,
-    should_push: bool,
-}
-
-impl ColorModes {
-    fn new() -> Self {
-        Self {
-            mode: 0,
-            blender: ColorBlender::new(0.01),
-            colors: vec![
-                [ // standard RGB
-                    [1.0, 0.0, 0.0].into(),
-                    [0.0, 1.0, 0.0].into(),
-                    [0.0, 0.0, 1.0].into(),
-                ],
-                [ // CMY
-                    [0.0, 1.0, 1.0].into(),
-                    [1.0, 0.0, 1.0].into(),
-                    [1.0, 1.0, 0.0].into(),
-                ],
-                [ // darker rgb
-                    [0.8, 0.0, 0.0].into(),
-                    [0.0, 0.8, 0.0].into(),
-                    [0.0, 0.0, 0.8].into(),
-                ],
-                [ // grayscale
-                    [0.0, 0.0, 0.0].into(),
-                    [0.5, 0.5, 0.5].into(),
-                    [1.0, 1.0, 1.0].into(),
-                ]
-            ].into_boxed_slice(),
-            blender_enabled: false,
-            should_push: true
+            let step = match (&self.metrics, self.stats_generation) {
+                (Some(_), Some(last)) => step.min(
+                    (last + self.stats_every)
+                        .saturating_sub(self.sim.generation)
+                        .max(1),
+                ),
+                _ => step,
+            };
+            self.sim.advance(step as u32);
+            ticks -= step;
+            self.record_frame();
         }
     }
-    fn tick(&mut self) {
-        if self.blender_enabled {
-            self.blender.step();
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if self.paused {
+            info!("Paused at generation {}", self.sim.generation);
         } else {
-            self.blender.reset(self.r_color(), self.p_color(), self.s_color());
-            self.should_push = true;
+            info!("Resumed");
         }
     }
-    #[inline]
-    fn r_color(&self) -> Color {
-        self.colors[self.mode][0]
-    }
-    #[inline]
-    fn p_color(&self) -> Color {
-        self.colors[self.mode][1]
-    }
-    #[inline]
-    fn s_color(&self) -> Color {
-        self.colors[self.mode][2]
-    }
-    fn colors(&self) -> [f32; 9] {
-        if self.blender_enabled {
-            self.blender.colors()
+    // stepping only makes sense while paused, so both of these pause first
+    fn step_forward(&mut self) {
+        self.paused = true;
+        self.advance(1);
+        info!("Generation {}", self.sim.generation);
+    }
+    fn step_back(&mut self) {
+        self.paused = true;
+        if self.sim.step_back() {
+            self.stats_generation = None;
+            info!("Generation {}", self.sim.generation);
         } else {
-            let colors = &self.colors[self.mode];
-            let rc = colors[0].color();
-            let pc = colors[1].color();
-            let sc = colors[2].color();
-            [
-                rc[0], rc[1], rc[2],
-                pc[0], pc[1], pc[2],
-                sc[0], sc[1], sc[2],
-            ]
+            info!("No more history to step back through");
         }
     }
-    fn push_to_gpu(&mut self, q: &mut wgpu::Queue, buffer: &wgpu::Buffer) {
-        if self.blender_enabled { 
-            self.blender.push_to_gpu(q, buffer);
-        } else if self.should_push {
-            self.should_push = false; // we should only push once per change
-            // this probably doesn't save any meaningful amount of time but i really dont care
-            q.write_buffer(buffer, 0, bytemuck::cast_slice(&self.colors()));
+    fn reset(&mut self) {
+        // same seed, so this goes back to exactly how things started
+        let state = self
+            .init
+            .generate(self.sim.width, self.sim.height, self.rule.species, self.seed);
+        match state {
+            Ok(state) => {
+                self.sim.write_state(&state);
+                self.stats_generation = None;
+                info!("Reset to {} with seed {}", self.init, self.seed);
+                // carry on recording from the start again
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.next_generation = self.sim.generation;
+                }
+                self.record_frame();
+            }
+            Err(e) => error!("Couldn't reset: {:?}", e),
         }
     }
-
-    fn next(&mut self) {
-        self.mode = (self.mode + 1) % self.colors.len();
-        self.should_push = true;
+    fn next_initial_state(&mut self) {
+        self.init = self.init.next();
+        self.reset();
     }
-    fn prev(&mut self) {
-        self.mode = (self.mode + self.colors.len() - 1) % self.colors.len();
-        self.should_push = true;
-    }
-    fn toggle_blender(&mut self) {
-        self.blender_enabled = !self.blender_enabled;
-    }
-    
-}
-
-
-struct ColorBlender {
-    rock: Color,
-    paper: Color,
-    scissors: Color,
-    rock_target: Color,
-    paper_target: Color,
-    scissors_target: Color,
-    dirty: bool,
-    has_done_step: bool,
-    pub blend_amt: f64,
-}
-
-impl ColorBlender {
-    fn new(blend_amt: f64) -> Self {
-        Self {
-            rock:     [1.0, 0.0, 0.0].into(),
-            paper:    [0.0, 1.0, 0.0].into(),
-            scissors: [0.0, 0.0, 1.0].into(),
-            dirty: false,
-            has_done_step: false,
-            rock_target: [1.0, 0.0, 0.0].into(),
-            paper_target: [0.0, 1.0, 0.0].into(),
-            scissors_target: [0.0, 0.0, 1.0].into(),
-            blend_amt
+    fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        // minimizing gives us a 0x0 window, which the surface can't be configured to
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        self.surface_config.width = size.width;
+        self.surface_config.height = size.height;
+        self.surface.configure(&self.sim.device, &self.surface_config);
+        self.update_view();
+    }
+    fn next_scale_mode(&mut self) {
+        self.scale_mode = self.scale_mode.next();
+        info!("Scale mode: {}", self.scale_mode);
+        self.update_view();
+    }
+    fn next_age_mode(&mut self) {
+        self.age_mode = self.age_mode.next();
+        // no point paying for ages that aren't drawn
+        if let Err(e) = self.sim.set_track_ages(self.age_mode != AgeMode::Off) {
+            error!("Couldn't track ages: {:?}", e);
+            self.age_mode = AgeMode::Off;
         }
+        info!("Age mode: {}", self.age_mode);
+        // the tracking buffer gets swapped out the first time there's room needed for ages
+        self.render_bind_groups = create_render_bind_groups(
+            &self.sim,
+            &self.render_bind_group_layout,
+            &self.color_buffer,
+            &self.view_buffer,
+        );
+        self.update_view();
     }
-    #[rustfmt::skip]
-    fn colors(&self) -> [f32; 9] {
-        let r=  self.rock.color();
-        let p = self.paper.color();
-        let s = self.scissors.color();
-        [
-            r[0], r[1], r[2],
-            p[0], p[1], p[2],
-            s[0], s[1], s[2],
-        ]
+    fn set_speed(&mut self, speed: usize) {
+        self.speed = speed.min(SPEEDS.len() - 1);
+        self.pending_ticks = 0.0;
+        info!("Speed: {}x", SPEEDS[self.speed]);
     }
-    fn reset(&mut self, to_r: Color, to_p: Color, to_s: Color) {
-        self.rock = to_r;
-        self.paper = to_p;
-        self.scissors = to_s;
-        self.rock_target = to_r;
-        self.paper_target = to_p;
-        self.scissors_target = to_s;
-        if self.has_done_step {
-            self.has_done_step = false;
-            self.dirty = true;
-        }
+    fn next_kernel(&mut self) {
+        self.sim.kernel = self.sim.kernel.next();
+        info!("Kernel: {}", self.sim.kernel);
     }
-    fn step(&mut self) {
-        self.has_done_step = true;
-        // if we've hit a target on any 3, reset to new random target
-        if (self.rock - self.rock_target).abs().sum() < self.blend_amt {
-            self.rock_target = self.rock_target.rand_within(0.5)
-        }
-        if (self.paper - self.paper_target).abs().sum() < self.blend_amt {
-            self.paper_target = self.paper_target.rand_within(0.5)
+    fn update_view(&self) {
+        let mut view = self.scale_mode.view(
+            (self.sim.width, self.sim.height),
+            (self.surface_config.width, self.surface_config.height),
+        );
+        view.age_mode = self.age_mode.shader_id();
+        self.sim.queue.write_buffer(&self.view_buffer, 0, bytemuck::bytes_of(&view));
+    }
+    /// Starts saving the grid as a png, one pixel per cell.
+    fn screenshot(&mut self) {
+        if self.pending_screenshot.is_some() {
+            info!("Still saving the last screenshot");
+            return;
         }
-        if (self.scissors - self.scissors_target).abs().sum() < self.blend_amt {
-            self.scissors_target = self.scissors_target.rand_within(0.5)
+        let name = format!(
+            "ow_my_eyes-{}-gen{}.png",
+            capture::timestamp(),
+            self.sim.generation
+        );
+        match self.capture_frame(self.sim.width, self.sim.height) {
+            Ok(pending) => self.pending_screenshot = Some((name, pending)),
+            Err(e) => error!("Couldn't take screenshot: {:?}", e),
         }
-        // blend towards target`
-        self.rock.blend_to_target(&self.rock_target, self.blend_amt);
-        self.paper.blend_to_target(&self.paper_target, self.blend_amt);
-        self.scissors.blend_to_target(&self.scissors_target, self.blend_amt);
-        self.dirty = true;
     }
-    fn push_to_gpu(&self, q: &mut wgpu::Queue, buffer: &wgpu::Buffer) {
-        if self.dirty {
-            let colors = self.colors();
-            q.write_buffer(buffer, 0, bytemuck::cast_slice(&colors));
+    fn finish_screenshot(&mut self) {
+        let Some((name, pending)) = &self.pending_screenshot else {
+            return;
+        };
+        let Some(frame) = pending.try_finish(&self.sim.device) else {
+            return;
+        };
+        let saved = frame
+            .and_then(|frame| frame.png())
+            .and_then(|png| capture::save_file(name, &png, "image/png"));
+        match saved {
+            Ok(()) => info!("Saved screenshot to {name}"),
+            Err(e) => error!("Couldn't save screenshot: {:?}", e),
         }
+        self.pending_screenshot = None;
     }
-}
-
-
-// COLOR MATH AAAAAAAA
-#[derive(Debug, Clone, Copy)]
-struct Color {
-    r: f64,
-    g: f64,
-    b: f64,
-}
-
-impl Color {
-    fn new(r: f64, g: f64, b: f64) -> Self {
-        Self { r, g, b }
-    }
-    fn color(&self) -> [f32; 3] {
-        [
-            self.r as f32, 
-            self.g as f32, 
-            self.b as f32
-        ]
-    }
-    fn abs(self) -> Self {
-        Self {
-            r: self.r.abs(),
-            g: self.g.abs(),
-            b: self.b.abs(),
+    /// Starts recording (or stops it), see [`Recorder`].
+    fn toggle_recording(&mut self) {
+        if self.recorder.is_some() {
+            self.stop_recording();
+            return;
         }
-    }
-    /*fn rand() -> Self {
-        let rng = &mut rand::thread_rng();
-        Self {
-            r: rng.gen_range(0.0..=1.0f64),
-            g: rng.gen_range(0.0..=1.0f64), 
-            b: rng.gen_range(0.0..=1.0f64),
+        // frames have to be waited on so none get dropped, and the browser can't wait
+        if cfg!(target_arch = "wasm32") {
+            error!("Recording doesn't work on the web");
+            return;
         }
-    
-    }*/
-    fn rand_within(&self, amt: f64) -> Self {
-        let r_min = (self.r - amt).min(1.0).max(0.0);
-        let r_max = (self.r + amt).min(1.0).max(0.0);
-        let g_min = (self.g - amt).min(1.0).max(0.0);
-        let g_max = (self.g + amt).min(1.0).max(0.0);
-        let b_min = (self.b - amt).min(1.0).max(0.0);
-        let b_max = (self.b + amt).min(1.0).max(0.0);
-        let rng = &mut rand::thread_rng();
-        Self {
-            r: rng.gen_range(r_min..=r_max),
-            g: rng.gen_range(g_min..=g_max),
-            b: rng.gen_range(b_min..=b_max),
+        let name = format!("ow_my_eyes-{}", capture::timestamp());
+        let recorder = Recorder::start(
+            self.record_options,
+            &name,
+            self.sim.width,
+            self.sim.height,
+            self.sim.generation,
+        );
+        match recorder {
+            Ok(recorder) => {
+                info!(
+                    "Recording every {} generations to {}",
+                    self.record_options.every,
+                    recorder.path.display()
+                );
+                self.recorder = Some(recorder);
+                self.record_frame();
+            }
+            Err(e) => error!("Couldn't start recording: {:?}", e),
         }
     }
-    fn sum(self) -> f64 {
-        self.r + self.g + self.b
-    }
-    fn blend_to_target(&mut self, target: &Self, amt: f64) {
-        // sub/add by amt for self to get closer (unless we're < amt away)
-        let diff_r = target.r - self.r;
-        let diff_g = target.g - self.g;
-        let diff_b = target.b - self.b;
-        if diff_r.abs() < amt {
-            self.r = target.r;
-        } else {
-            self.r += diff_r.signum() * amt;
+    fn stop_recording(&mut self) {
+        let Some(recorder) = self.recorder.take() else {
+            return;
+        };
+        let (frames, path) = (recorder.frames, recorder.path.clone());
+        match recorder.finish() {
+            Ok(()) => info!("Recorded {frames} frames to {}", path.display()),
+            Err(e) => error!("Couldn't finish recording: {:?}", e),
         }
-        if diff_g.abs() < amt {
-            self.g = target.g;
-        } else {
-            self.g += diff_g.signum() * amt;
+    }
+    /// Adds the current generation to the recording, if it's one the recording wants.
+    fn record_frame(&mut self) {
+        let due = self
+            .recorder
+            .as_ref()
+            .is_some_and(|recorder| self.sim.generation >= recorder.next_generation);
+        if !due {
+            return;
         }
-        if diff_b.abs() < amt {
-            self.b = target.b;
-        } else {
-            self.b += diff_b.signum() * amt;
+        let (width, height) = self.record_options.frame_size(self.sim.width, self.sim.height);
+        // waiting here keeps every frame, even when it slows the game down
+        let frame = self
+            .capture_frame(width, height)
+            .and_then(|pending| pending.wait(&self.sim.device));
+        let written = frame.and_then(|frame| match &mut self.recorder {
+            Some(recorder) => recorder.write(&frame),
+            None => Ok(()),
+        });
+        if let Err(e) = written {
+            error!("Couldn't record generation {}: {:?}", self.sim.generation, e);
+            self.stop_recording();
         }
     }
-}
+    /// Draws the whole grid into an offscreen `width` x `height` texture and starts reading it back.
+    fn capture_frame(&self, width: u32, height: u32) -> anyhow::Result<PendingFrame> {
+        let device = &self.sim.device;
+        let max = device.limits().max_texture_dimension_2d;
+        anyhow::ensure!(
+            width <= max && height <= max,
+            "a {width}x{height} frame is too big to capture, the device's textures go up to {max}x{max}"
+        );
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // has to match the render pipeline, which is set up for the surface
+            format: self.surface_config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        // the grid fills the whole texture, coloured in the same as on screen
+        let view = View {
+            offset: [0.0, 0.0],
+            size: [width as f32, height as f32],
+            age_mode: self.age_mode.shader_id(),
+            _pad: 0,
+        };
+        let view_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            usage: wgpu::BufferUsages::UNIFORM,
+            label: Some("Capture View Buffer"),
+            contents: bytemuck::bytes_of(&view),
+        });
+        let bind_group = create_render_bind_group(
+            &self.sim,
+            self.sim.current,
+            &self.render_bind_group_layout,
+            &self.color_buffer,
+            &view_buffer,
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.draw(&mut encoder, &texture_view, &bind_group);
+        self.sim.queue.submit(Some(encoder.finish()));
+        PendingFrame::start(device, &self.sim.queue, &texture)
+    }
+    fn draw(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, bind_group: &wgpu::BindGroup) {
+        let render_pass_desc = wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.1,
+                        g: 0.2,
+                        b: 0.3,
+                        a: 1.0,
+                    }),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        };
+        let mut render_pass = encoder.begin_render_pass(&render_pass_desc);
 
-impl From<[f64; 3]> for Color {
-    fn from(arr: [f64; 3]) -> Self {
-        Self {
-            r: arr[0],
-            g: arr[1],
-            b: arr[2],
-        }
-    }
-}
+        render_pass.set_pipeline(&self.render_pipeline);
 
-impl std::ops::Sub for Color {
-    type Output = Color;
-    fn sub(self, rhs: Self) -> Self::Output {
-        Color::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
     }
-}
-impl std::ops::Sub<&Color> for Color {
-    type Output = Color;
-    fn sub(self, rhs: &Self) -> Self::Output {
-        Color::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    fn render(&mut self) -> anyhow::Result<()> {
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            // happens when the window changes under us, just try again next frame
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.surface.configure(&self.sim.device, &self.surface_config);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let texture_view = output.texture.create_view(&wgpu::TextureViewDescriptor {
+            ..Default::default()
+        });
+        let mut encoder = self
+            .sim
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Command Encoder"),
+            });
+        // draw whichever buffer the last tick wrote to
+        self.draw(&mut encoder, &texture_view, &self.render_bind_groups[self.sim.current]);
+        self.color_manager.push_to_gpu(&self.sim.queue, &self.color_buffer);
+        self.sim.queue.submit(Some(encoder.finish()));
+        //self.device.poll(wgpu::Maintain::WaitForSubmissionIndex(id));
+        output.present();
+        Ok(())
     }
 }
 
-impl std::ops::Sub<&Color> for &Color {
-    type Output = Color;
-    fn sub(self, rhs: &Color) -> Self::Output {
-        Color::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
-    }
-}
\ No newline at end of file
+// one for each of the ping-pong buffers
+fn create_render_bind_groups(
+    sim: &GpuSimulation,
+    layout: &wgpu::BindGroupLayout,
+    color_buffer: &wgpu::Buffer,
+    view_buffer: &wgpu::Buffer,
+) -> [wgpu::BindGroup; 2] {
+    [0, 1].map(|i| create_render_bind_group(sim, i, layout, color_buffer, view_buffer))
+}
+
+/// Draws `sim.data_buffers[buffer]`.
+fn create_render_bind_group(
+    sim: &GpuSimulation,
+    buffer: usize,
+    layout: &wgpu::BindGroupLayout,
+    color_buffer: &wgpu::Buffer,
+    view_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    sim.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Render Bind Group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: sim.data_buffers[buffer].as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 2,
+            resource: color_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 3,
+            resource: sim.params_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 4,
+            resource: view_buffer.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            binding: 7,
+            resource: sim.tracking_buffer.as_entire_binding(),
+        }],
+    })
+}
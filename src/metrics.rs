@@ -0,0 +1,170 @@
+// writing stats out as a time series, for looking at in a notebook afterwards
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::Stats;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricsFormat {
+    /// A header row, then `generation,species_0,..,changed,tick_ms`.
+    Csv,
+    /// One object per line, `{"generation":..,"counts":[..],"changed":..,"tick_ms":..}`.
+    Ndjson,
+}
+
+/// Writes a line for every [`Stats`] it's given. Changed cells and tick time are left empty
+/// (or `null`) for generations that weren't ticked to, like right after a reset.
+pub(crate) struct MetricsWriter {
+    format: MetricsFormat,
+    out: BufWriter<File>,
+    pub path: PathBuf,
+    species: u32,
+}
+
+impl MetricsWriter {
+    /// `.csv` for csv, `.ndjson`, `.jsonl` or `.json` for newline delimited json.
+    pub fn create(path: &Path, species: u32) -> anyhow::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let format = match extension.as_str() {
+            "csv" => MetricsFormat::Csv,
+            "ndjson" | "jsonl" | "json" => MetricsFormat::Ndjson,
+            _ => anyhow::bail!(
+                "Don't know how to write metrics to {}, it should end in .csv or .ndjson",
+                path.display()
+            ),
+        };
+        let file =
+            File::create(path).with_context(|| format!("Couldn't create {}", path.display()))?;
+        let mut writer = Self {
+            format,
+            out: BufWriter::new(file),
+            path: path.to_owned(),
+            species,
+        };
+        if format == MetricsFormat::Csv {
+            let mut header = String::from("generation");
+            for s in 0..species {
+                header += &format!(",species_{s}");
+            }
+            header += ",changed,tick_ms";
+            writer.line(&header)?;
+        }
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, stats: &Stats) -> anyhow::Result<()> {
+        // the columns can't change partway through
+        anyhow::ensure!(
+            stats.counts.len() == self.species as usize,
+            "Metrics are for {} species, but there's {} now",
+            self.species,
+            stats.counts.len()
+        );
+        let counts: Vec<String> = stats.counts.iter().map(u64::to_string).collect();
+        let tick_ms = stats.tick_time.map(|t| t.as_secs_f64() * 1000.0);
+        let line = match self.format {
+            MetricsFormat::Csv => format!(
+                "{},{},{},{}",
+                stats.generation,
+                counts.join(","),
+                stats.changed.map(|c| c.to_string()).unwrap_or_default(),
+                tick_ms.map(|t| t.to_string()).unwrap_or_default()
+            ),
+            MetricsFormat::Ndjson => format!(
+                r#"{{"generation":{},"counts":[{}],"changed":{},"tick_ms":{}}}"#,
+                stats.generation,
+                counts.join(","),
+                stats.changed.map_or("null".into(), |c| c.to_string()),
+                tick_ms.map_or("null".into(), |t| t.to_string())
+            ),
+        };
+        self.line(&line)
+    }
+
+    fn line(&mut self, line: &str) -> anyhow::Result<()> {
+        writeln!(self.out, "{line}").with_context(|| format!("Couldn't write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Writes `stats` to a temporary file ending in `extension`, and gives back what ended up in it.
+    fn write(extension: &str, species: u32, stats: &[Stats]) -> anyhow::Result<String> {
+        let path = std::env::temp_dir().join(format!(
+            "ow_my_eyes-test-{}-{species}.{extension}",
+            std::process::id()
+        ));
+        let result = (|| {
+            let mut writer = MetricsWriter::create(&path, species)?;
+            for stats in stats {
+                writer.write(stats)?;
+            }
+            drop(writer);
+            Ok(std::fs::read_to_string(&path)?)
+        })();
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    fn stats() -> [Stats; 2] {
+        [
+            // the first generation didn't come from a tick
+            Stats {
+                generation: 0,
+                counts: vec![5, 3, 2],
+                changed: None,
+                tick_time: None,
+            },
+            Stats {
+                generation: 1,
+                counts: vec![4, 4, 2],
+                changed: Some(3),
+                tick_time: Some(Duration::from_micros(1500)),
+            },
+        ]
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            write("csv", 3, &stats()).unwrap(),
+            "generation,species_0,species_1,species_2,changed,tick_ms\n0,5,3,2,,\n1,4,4,2,3,1.5\n"
+        );
+    }
+
+    #[test]
+    fn ndjson() {
+        let expected = concat!(
+            r#"{"generation":0,"counts":[5,3,2],"changed":null,"tick_ms":null}"#,
+            "\n",
+            r#"{"generation":1,"counts":[4,4,2],"changed":3,"tick_ms":1.5}"#,
+            "\n",
+        );
+        for extension in ["ndjson", "jsonl", "json", "NDJSON"] {
+            assert_eq!(write(extension, 3, &stats()).unwrap(), expected, "{extension}");
+        }
+    }
+
+    #[test]
+    fn formats() {
+        for extension in ["txt", "csv.gz", ""] {
+            assert!(write(extension, 3, &[]).is_err(), "{extension}");
+        }
+        // the columns can't change partway through
+        assert!(write("csv", 4, &stats()).is_err());
+    }
+}
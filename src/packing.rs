@@ -0,0 +1,128 @@
+/// How cells get packed into `u32`s on the gpu.
+///
+/// Every cell takes [`bits`](Self::bits) bits, the smallest power of two that fits every species
+/// (so 2 bits and 16 cells per word for rock, paper, scissors). Rows are packed left to right
+/// starting from the lowest bits, and every row starts on a fresh word, so cell `(x, y)` lives in
+/// word `y * row_words + x / cells_per_word`, shifted up by `(x % cells_per_word) * bits`.
+/// Any bits past the end of a row are 0.
+///
+/// keep in sync with `word_index` and `unpack` in shader.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellLayout {
+    pub width: u32,
+    pub height: u32,
+    pub bits: u32,
+}
+
+impl CellLayout {
+    pub fn new(width: u32, height: u32, species: u32) -> Self {
+        let bits = [1, 2, 4, 8, 16]
+            .into_iter()
+            .find(|bits| species <= 1 << bits)
+            .unwrap_or(32);
+        Self {
+            width,
+            height,
+            bits,
+        }
+    }
+
+    pub fn cells_per_word(self) -> u32 {
+        32 / self.bits
+    }
+
+    /// Words in every row, including the padding at the end.
+    pub fn row_words(self) -> u32 {
+        self.width.div_ceil(self.cells_per_word())
+    }
+
+    /// Words in the whole grid.
+    pub fn words(self) -> usize {
+        self.row_words() as usize * self.height as usize
+    }
+
+    /// Packs a row-major grid with one `u32` per cell.
+    pub fn pack(self, cells: &[u32]) -> Vec<u32> {
+        let (width, cpw) = (self.width as usize, self.cells_per_word() as usize);
+        let mut words = vec![0; self.words()];
+        for (y, row) in cells.chunks(width).enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                words[y * self.row_words() as usize + x / cpw] |=
+                    cell << ((x % cpw) as u32 * self.bits);
+            }
+        }
+        words
+    }
+
+    /// Unpacks back into a row-major grid with one `u32` per cell.
+    pub fn unpack(self, words: &[u32]) -> Vec<u32> {
+        let (width, cpw) = (self.width as usize, self.cells_per_word() as usize);
+        // u64 so 32 bit cells don't overflow the shift
+        let mask = ((1u64 << self.bits) - 1) as u32;
+        words
+            .chunks(self.row_words() as usize)
+            .flat_map(|row| {
+                (0..width).map(move |x| (row[x / cpw] >> ((x % cpw) as u32 * self.bits)) & mask)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every cell a different species (as far as the bits go), so a cell in the wrong place shows.
+    fn cells(width: u32, height: u32, species: u32) -> Vec<u32> {
+        (0..width * height).map(|i| (i * 7 + i / 3) % species).collect()
+    }
+
+    #[test]
+    fn bits() {
+        assert_eq!(CellLayout::new(1, 1, 2).bits, 1);
+        assert_eq!(CellLayout::new(1, 1, 3).bits, 2);
+        assert_eq!(CellLayout::new(1, 1, 4).bits, 2);
+        assert_eq!(CellLayout::new(1, 1, 5).bits, 4);
+        assert_eq!(CellLayout::new(1, 1, 16).bits, 4);
+        assert_eq!(CellLayout::new(1, 1, 17).bits, 8);
+    }
+
+    #[test]
+    fn round_trips() {
+        for species in [2, 3, 4, 5, 16] {
+            // widths either side of a whole number of words for 1, 2 and 4 bits
+            for width in [1, 7, 8, 9, 15, 16, 17, 31, 32, 33, 100] {
+                let layout = CellLayout::new(width, 5, species);
+                let cells = cells(width, 5, species);
+                let words = layout.pack(&cells);
+                assert_eq!(words.len(), layout.words());
+                assert_eq!(layout.unpack(&words), cells, "{width} wide, {species} species");
+            }
+        }
+    }
+
+    #[test]
+    fn layout() {
+        // 2 bits, 16 cells per word, so 17 wide needs 2 words a row
+        let layout = CellLayout::new(17, 2, 3);
+        assert_eq!((layout.cells_per_word(), layout.row_words(), layout.words()), (16, 2, 4));
+        let mut cells = vec![0; 34];
+        cells[1] = 2;
+        cells[16] = 1;
+        cells[17] = 2;
+        let words = layout.pack(&cells);
+        // every row starts on a fresh word, lowest bits first
+        assert_eq!(words, [2 << 2, 1, 2, 0]);
+    }
+
+    #[test]
+    fn padding_is_zero() {
+        for species in [2, 3, 5] {
+            let layout = CellLayout::new(9, 3, species);
+            let words = layout.pack(&[species - 1; 27]);
+            let used = 9 - (layout.row_words() - 1) * layout.cells_per_word();
+            let last = words[layout.row_words() as usize - 1];
+            assert_eq!(last >> (used * layout.bits), 0, "{species} species");
+        }
+    }
+}
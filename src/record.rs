@@ -0,0 +1,183 @@
+// dumping every nth generation to disk, so videos don't need a screen recorder
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+
+use crate::capture::Frame;
+
+/// What a recording gets written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum RecordFormat {
+    /// A folder of numbered pngs.
+    #[default]
+    Png,
+    /// One uncompressed yuv4mpeg2 stream, 4:4:4 so the colours don't bleed.
+    Y4m,
+}
+
+impl std::fmt::Display for RecordFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Png => "png",
+            Self::Y4m => "y4m",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for RecordFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "png" => Ok(Self::Png),
+            "y4m" => Ok(Self::Y4m),
+            _ => anyhow::bail!("Unknown record format: {s} (expected png or y4m)"),
+        }
+    }
+}
+
+/// How recordings get made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RecordOptions {
+    pub format: RecordFormat,
+    /// Keep one generation out of every this many.
+    pub every: u32,
+    /// Shrink frames by this much each way. Cells get picked rather than blended,
+    /// so the colours stay exactly the palette's.
+    pub downscale: u32,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        Self {
+            format: RecordFormat::default(),
+            every: 1,
+            downscale: 1,
+        }
+    }
+}
+
+impl RecordOptions {
+    /// How big the frames are for a `width` x `height` grid.
+    pub fn frame_size(&self, width: u32, height: u32) -> (u32, u32) {
+        ((width / self.downscale).max(1), (height / self.downscale).max(1))
+    }
+}
+
+enum Sink {
+    /// Files go in the recording's folder.
+    Png,
+    Y4m(BufWriter<File>),
+}
+
+/// A recording in progress. Frames get taken by generation rather than by time,
+/// so the same run always records the same frames however fast it's going.
+pub(crate) struct Recorder {
+    options: RecordOptions,
+    sink: Sink,
+    /// The folder or file it's going to.
+    pub path: PathBuf,
+    width: u32,
+    height: u32,
+    pub frames: u64,
+    /// The generation the next frame gets taken at.
+    pub next_generation: u64,
+}
+
+impl Recorder {
+    /// Starts a recording named after `name` (a folder for pngs, `name.y4m` otherwise),
+    /// with the first frame at `generation`.
+    pub fn start(
+        options: RecordOptions,
+        name: &str,
+        width: u32,
+        height: u32,
+        generation: u64,
+    ) -> anyhow::Result<Self> {
+        let (width, height) = options.frame_size(width, height);
+        let (path, sink) = match options.format {
+            RecordFormat::Png => {
+                let path = PathBuf::from(name);
+                std::fs::create_dir_all(&path)
+                    .with_context(|| format!("Couldn't create {}", path.display()))?;
+                (path, Sink::Png)
+            }
+            RecordFormat::Y4m => {
+                let path = PathBuf::from(format!("{name}.y4m"));
+                let file = File::create(&path)
+                    .with_context(|| format!("Couldn't create {}", path.display()))?;
+                let mut out = BufWriter::new(file);
+                // the game updates 60 times a second, so play it back at that
+                writeln!(out, "YUV4MPEG2 W{width} H{height} F60:1 Ip A1:1 C444")
+                    .with_context(|| format!("Couldn't write {}", path.display()))?;
+                (path, Sink::Y4m(out))
+            }
+        };
+        Ok(Self {
+            options,
+            sink,
+            path,
+            width,
+            height,
+            frames: 0,
+            next_generation: generation,
+        })
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (frame.width, frame.height) == (self.width, self.height),
+            "Frame is {}x{}, but the recording is {}x{}",
+            frame.width,
+            frame.height,
+            self.width,
+            self.height
+        );
+        match &mut self.sink {
+            Sink::Png => {
+                // numbered by frame rather than generation so ffmpeg's %06d picks them all up
+                let path = self.path.join(format!("frame-{:06}.png", self.frames));
+                std::fs::write(&path, frame.png()?)
+                    .with_context(|| format!("Couldn't write {}", path.display()))?;
+            }
+            Sink::Y4m(out) => {
+                out.write_all(b"FRAME\n")
+                    .and_then(|()| out.write_all(&ycbcr_planes(frame)))
+                    .with_context(|| format!("Couldn't write {}", self.path.display()))?;
+            }
+        }
+        self.frames += 1;
+        self.next_generation += self.options.every as u64;
+        Ok(())
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        if let Sink::Y4m(mut out) = self.sink {
+            out.flush()
+                .with_context(|| format!("Couldn't write {}", self.path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The frame as y, cb and cr planes one after the other. Studio range bt.601, which is what
+/// everything assumes a y4m is when it doesn't say.
+fn ycbcr_planes(frame: &Frame) -> Vec<u8> {
+    let pixels = (frame.width * frame.height) as usize;
+    let mut planes = vec![0; pixels * 3];
+    let (y, rest) = planes.split_at_mut(pixels);
+    let (cb, cr) = rest.split_at_mut(pixels);
+    for (i, pixel) in frame.rgba.chunks_exact(4).enumerate() {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(i32::from);
+        y[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        cb[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        cr[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    planes
+}
@@ -0,0 +1,626 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::Context;
+
+/// Biggest radius an extended moore neighbourhood can have.
+pub const MAX_RADIUS: u32 = 8;
+/// Most species a rule can have.
+pub const MAX_SPECIES: u32 = 16;
+/// Highest [`Rule::max_score`] a rule can have. There's an entry in the odds table for every
+/// score up to it, and the shader adds scores up in a `u32`, so this keeps both in hand.
+pub const MAX_SCORE: u32 = 1 << 16;
+
+/// Which cells count as neighbours when deciding if a cell gets taken over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Neighbourhood {
+    /// The 4 cells sharing an edge.
+    VonNeumann,
+    /// The 8 surrounding cells, the original rule.
+    #[default]
+    Moore,
+    /// Every cell within `radius` in both directions, `(2r + 1)^2 - 1` of them.
+    ExtendedMoore { radius: u32 },
+    /// 6 neighbours, treating every odd row as shifted half a cell to the right.
+    Hexagonal,
+}
+
+impl Neighbourhood {
+    /// How far out the neighbourhood reaches.
+    pub fn radius(self) -> u32 {
+        match self {
+            Self::ExtendedMoore { radius } => radius,
+            _ => 1,
+        }
+    }
+
+    /// Whether the cell at `(dx, dy)` from a cell in row `y` is one of its neighbours.
+    /// keep in sync with `in_neighbourhood` in shader.wgsl
+    pub fn contains(self, dx: i32, dy: i32, y: u32) -> bool {
+        let r = self.radius() as i32;
+        if (dx == 0 && dy == 0) || dx.abs() > r || dy.abs() > r {
+            return false;
+        }
+        match self {
+            Self::VonNeumann => dx.abs() + dy.abs() <= 1,
+            Self::Moore | Self::ExtendedMoore { .. } => true,
+            // odd rows are shifted right, so the rows above and below lean left on even rows and right on odd ones
+            Self::Hexagonal => dy == 0 || if y.is_multiple_of(2) { dx <= 0 } else { dx >= 0 },
+        }
+    }
+
+    /// Offsets of every neighbour of a cell in row `y`.
+    pub fn offsets(self, y: u32) -> Vec<(i32, i32)> {
+        let r = self.radius() as i32;
+        (-r..=r)
+            .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+            .filter(|&(dx, dy)| self.contains(dx, dy, y))
+            .collect()
+    }
+
+    /// How many neighbours every cell has.
+    pub fn size(self) -> u32 {
+        self.offsets(0).len() as u32
+    }
+
+    /// The original rule needs more than 2 of 8 neighbours to win, this scales that to the same fraction.
+    pub fn default_threshold(self) -> u32 {
+        (self.size() * 3).div_ceil(8) - 1
+    }
+
+    // keep in sync with the consts in shader.wgsl
+    pub(crate) fn shader_id(self) -> u32 {
+        match self {
+            Self::VonNeumann => 0,
+            Self::Moore => 1,
+            Self::ExtendedMoore { .. } => 2,
+            Self::Hexagonal => 3,
+        }
+    }
+}
+
+impl fmt::Display for Neighbourhood {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VonNeumann => write!(f, "von-neumann"),
+            Self::Moore => write!(f, "moore"),
+            Self::ExtendedMoore { radius } => write!(f, "moore:{radius}"),
+            Self::Hexagonal => write!(f, "hex"),
+        }
+    }
+}
+
+impl FromStr for Neighbourhood {
+    type Err = anyhow::Error;
+
+    /// Parses `von-neumann`, `moore`, `moore:<radius>` or `hex`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let neighbourhood = match s {
+            "von-neumann" | "vn" => Self::VonNeumann,
+            "moore" => Self::Moore,
+            "hex" | "hexagonal" => Self::Hexagonal,
+            _ => {
+                let radius = s
+                    .strip_prefix("moore:")
+                    .with_context(|| format!("Unknown neighbourhood: {s}"))?;
+                let radius = radius
+                    .parse()
+                    .with_context(|| format!("Bad radius: {radius}"))?;
+                anyhow::ensure!(
+                    (1..=MAX_RADIUS).contains(&radius),
+                    "Radius has to be between 1 and {MAX_RADIUS}, got {radius}"
+                );
+                Self::ExtendedMoore { radius }
+            }
+        };
+        Ok(neighbourhood)
+    }
+}
+
+/// What neighbours past the edge of the grid look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Boundary {
+    /// There's nothing there, so edge cells just have fewer neighbours. The original behaviour.
+    #[default]
+    Clipped,
+    /// Wraps around to the other side.
+    Toroidal,
+    /// Mirrors the grid back onto itself at the edge.
+    Reflective,
+    /// Everything outside is this species.
+    Fixed(u32),
+}
+
+impl Boundary {
+    /// Works out what the neighbour at `(x, y)` is, which might be off the grid.
+    /// keep in sync with `neighbour_state` in shader.wgsl
+    pub(crate) fn resolve(self, x: i32, y: i32, width: u32, height: u32) -> Neighbour {
+        let (w, h) = (width as i32, height as i32);
+        if (0..w).contains(&x) && (0..h).contains(&y) {
+            return Neighbour::Cell(x as u32, y as u32);
+        }
+        match self {
+            Self::Clipped => Neighbour::Missing,
+            Self::Fixed(species) => Neighbour::Border(species),
+            Self::Toroidal => Neighbour::Cell(x.rem_euclid(w) as u32, y.rem_euclid(h) as u32),
+            Self::Reflective => Neighbour::Cell(reflect(x, w), reflect(y, h)),
+        }
+    }
+
+    // keep in sync with the consts in shader.wgsl
+    pub(crate) fn shader_id(self) -> u32 {
+        match self {
+            Self::Clipped => 0,
+            Self::Toroidal => 1,
+            Self::Reflective => 2,
+            Self::Fixed(_) => 3,
+        }
+    }
+
+    pub(crate) fn border_species(self) -> u32 {
+        match self {
+            Self::Fixed(species) => species,
+            _ => 0,
+        }
+    }
+}
+
+pub(crate) enum Neighbour {
+    /// A cell on the grid.
+    Cell(u32, u32),
+    /// Off the grid, but counts as this species.
+    Border(u32),
+    /// Off the grid and doesn't count.
+    Missing,
+}
+
+// -1 -> 0, -2 -> 1, size -> size - 1 and so on
+// the clamp is for neighbourhoods bigger than the grid
+fn reflect(t: i32, size: i32) -> u32 {
+    let t = if t < 0 { -t - 1 } else if t >= size { 2 * size - t - 1 } else { t };
+    t.clamp(0, size - 1) as u32
+}
+
+impl fmt::Display for Boundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Clipped => write!(f, "clipped"),
+            Self::Toroidal => write!(f, "toroidal"),
+            Self::Reflective => write!(f, "reflective"),
+            Self::Fixed(species) => write!(f, "fixed:{species}"),
+        }
+    }
+}
+
+impl FromStr for Boundary {
+    type Err = anyhow::Error;
+
+    /// Parses `clipped`, `toroidal`, `reflective` or `fixed:<species>`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let boundary = match s {
+            "clipped" => Self::Clipped,
+            "toroidal" | "torus" | "wrap" => Self::Toroidal,
+            "reflective" | "mirror" => Self::Reflective,
+            _ => {
+                let species = s
+                    .strip_prefix("fixed:")
+                    .with_context(|| format!("Unknown boundary: {s}"))?;
+                let species = species
+                    .parse()
+                    .with_context(|| format!("Bad species: {species}"))?;
+                Self::Fixed(species)
+            }
+        };
+        Ok(boundary)
+    }
+}
+
+/// What happens once a cell has more winning neighbours than the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Takeover {
+    /// It always gets taken over.
+    #[default]
+    Deterministic,
+    /// Every winning neighbour gets one shot at taking it over, each succeeding with `probability`.
+    /// So with `n` winning neighbours it gets taken over with probability `1 - (1 - probability)^n`.
+    Stochastic { probability: f64 },
+}
+
+/// Who beats whom.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Dominance {
+    /// Each species is beaten by the next one, and the last by the first. Rock, paper, scissors.
+    #[default]
+    Cyclic,
+    /// `weights[a * species + b]` is how much a neighbour of species `a` counts towards taking over
+    /// a cell of species `b`. 0 means `a` doesn't beat `b` at all, 1 is a normal win.
+    Matrix { weights: Vec<u32> },
+}
+
+impl Dominance {
+    /// How many species this matrix is for, if it's fixed.
+    pub fn species(&self) -> Option<u32> {
+        match self {
+            Self::Cyclic => None,
+            Self::Matrix { weights } => Some((weights.len() as f64).sqrt() as u32),
+        }
+    }
+}
+
+impl fmt::Display for Dominance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cyclic => write!(f, "cyclic"),
+            Self::Matrix { weights } => {
+                let n = self.species().unwrap_or(0) as usize;
+                let rows: Vec<String> = weights
+                    .chunks(n.max(1))
+                    .map(|row| row.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(","))
+                    .collect();
+                write!(f, "{}", rows.join(";"))
+            }
+        }
+    }
+}
+
+impl FromStr for Dominance {
+    type Err = anyhow::Error;
+
+    /// Parses `cyclic`, or a square matrix with rows split by `;` (or newlines) and weights split by `,`.
+    /// Row `a`, column `b` is how much `a` beats `b`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.trim() == "cyclic" {
+            return Ok(Self::Cyclic);
+        }
+        let rows = s
+            .split([';', '\n'])
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .map(|row| {
+                row.split(',')
+                    .map(|w| w.trim().parse::<u32>().with_context(|| format!("Bad weight: {w}")))
+                    .collect::<anyhow::Result<Vec<_>>>()
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(
+            rows.iter().all(|row| row.len() == rows.len()),
+            "Dominance matrix has to be square"
+        );
+        Ok(Self::Matrix {
+            weights: rows.concat(),
+        })
+    }
+}
+
+/// `odds` are out of this, and rolls are between 0 and this
+pub(crate) const ODDS_ONE: u32 = 1 << 24;
+
+/// Everything that decides how a generation turns into the next one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// How many species there are.
+    pub species: u32,
+    pub dominance: Dominance,
+    pub neighbourhood: Neighbourhood,
+    pub boundary: Boundary,
+    /// Overrides [`Neighbourhood::default_threshold`].
+    pub threshold: Option<u32>,
+    pub takeover: Takeover,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            species: 3,
+            dominance: Dominance::default(),
+            neighbourhood: Neighbourhood::default(),
+            boundary: Boundary::default(),
+            threshold: None,
+            takeover: Takeover::default(),
+        }
+    }
+}
+
+impl Rule {
+    /// Makes sure the rule makes sense before anything gets simulated with it.
+    pub fn check(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (2..=MAX_SPECIES).contains(&self.species),
+            "Species has to be between 2 and {MAX_SPECIES}, got {}",
+            self.species
+        );
+        anyhow::ensure!(
+            self.neighbourhood.radius() <= MAX_RADIUS,
+            "Radius can be at most {MAX_RADIUS}, got {}",
+            self.neighbourhood.radius()
+        );
+        if let Dominance::Matrix { weights } = &self.dominance {
+            anyhow::ensure!(
+                weights.len() == (self.species * self.species) as usize,
+                "Dominance matrix has to be {0}x{0} for {0} species",
+                self.species
+            );
+            let max_weight = weights.iter().copied().max().unwrap_or(0);
+            let max_score = self.neighbourhood.size().checked_mul(max_weight);
+            anyhow::ensure!(
+                max_score.is_some_and(|score| score <= MAX_SCORE),
+                "Dominance weights are too big, a cell can score at most {MAX_SCORE} ({} neighbours \
+                 with weight {max_weight} is more)",
+                self.neighbourhood.size()
+            );
+        }
+        if let Boundary::Fixed(species) = self.boundary {
+            anyhow::ensure!(
+                species < self.species,
+                "Border species has to be less than {}, got {species}",
+                self.species
+            );
+        }
+        Ok(())
+    }
+
+    /// Who beats whom as a flat `species * species` matrix, see [`Dominance::Matrix`].
+    pub fn dominance_matrix(&self) -> Vec<u32> {
+        let n = self.species;
+        match &self.dominance {
+            Dominance::Cyclic => (0..n * n)
+                .map(|i| {
+                    let (them, us) = (i / n, i % n);
+                    // each species is beaten by the next one
+                    u32::from(them == (us + 1) % n)
+                })
+                .collect(),
+            Dominance::Matrix { weights } => weights.clone(),
+        }
+    }
+
+    /// The most a single species can score against a cell, which is when every neighbour is
+    /// the species that beats it the hardest. At most [`MAX_SCORE`] once [`check`](Self::check) passes.
+    pub fn max_score(&self) -> u32 {
+        let max_weight = self.dominance_matrix().into_iter().max().unwrap_or(0);
+        self.neighbourhood.size().saturating_mul(max_weight)
+    }
+
+    /// A cell gets taken over when more than this many neighbours beat it.
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+            .unwrap_or_else(|| self.neighbourhood.default_threshold())
+    }
+
+    /// Chance of getting taken over for every possible score, out of [`ODDS_ONE`].
+    /// Worked out here instead of in the shader so the gpu and cpu agree down to the last bit.
+    pub(crate) fn odds(&self) -> Vec<u32> {
+        (0..=self.max_score())
+            .map(|win_count| match self.takeover {
+                Takeover::Deterministic => ODDS_ONE,
+                Takeover::Stochastic { probability } => {
+                    let p = 1.0 - (1.0 - probability.clamp(0.0, 1.0)).powi(win_count as i32);
+                    (p * ODDS_ONE as f64).round() as u32
+                }
+            })
+            .collect()
+    }
+}
+
+/// The per-cell random number for stochastic takeovers, between 0 and [`ODDS_ONE`].
+/// keep in sync with `roll` in shader.wgsl
+pub(crate) fn roll(cell: u32, generation: u32) -> u32 {
+    pcg(cell ^ pcg(generation)) >> 8
+}
+
+// https://www.jcgt.org/published/0009/03/02/
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} species ({}), {} (threshold {}), {} edges",
+            self.species,
+            self.dominance,
+            self.neighbourhood,
+            self.threshold(),
+            self.boundary
+        )?;
+        if let Takeover::Stochastic { probability } = self.takeover {
+            write!(f, ", stochastic (p = {probability})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbourhood_sizes() {
+        assert_eq!(Neighbourhood::VonNeumann.size(), 4);
+        assert_eq!(Neighbourhood::Moore.size(), 8);
+        assert_eq!(Neighbourhood::Hexagonal.size(), 6);
+        for radius in 1..=MAX_RADIUS {
+            let n = 2 * radius + 1;
+            assert_eq!(Neighbourhood::ExtendedMoore { radius }.size(), n * n - 1);
+        }
+        // hex rows lean opposite ways, but always have the same number of neighbours
+        assert_eq!(
+            Neighbourhood::Hexagonal.offsets(0),
+            [(-1, -1), (0, -1), (-1, 0), (1, 0), (-1, 1), (0, 1)]
+        );
+        assert_eq!(
+            Neighbourhood::Hexagonal.offsets(1),
+            [(0, -1), (1, -1), (-1, 0), (1, 0), (0, 1), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn default_thresholds() {
+        // the original rule is more than 2 of 8
+        assert_eq!(Neighbourhood::Moore.default_threshold(), 2);
+        assert_eq!(Neighbourhood::VonNeumann.default_threshold(), 1);
+        assert_eq!(Neighbourhood::Hexagonal.default_threshold(), 2);
+        assert_eq!(Neighbourhood::ExtendedMoore { radius: 1 }.default_threshold(), 2);
+        assert_eq!(Neighbourhood::ExtendedMoore { radius: 2 }.default_threshold(), 8);
+        assert_eq!(Neighbourhood::ExtendedMoore { radius: 8 }.default_threshold(), 107);
+        let rule = Rule {
+            neighbourhood: Neighbourhood::VonNeumann,
+            ..Rule::default()
+        };
+        assert_eq!(rule.threshold(), 1);
+        assert_eq!(Rule { threshold: Some(5), ..rule }.threshold(), 5);
+    }
+
+    fn resolve(boundary: Boundary, x: i32, y: i32) -> Option<(u32, u32)> {
+        match boundary.resolve(x, y, 4, 3) {
+            Neighbour::Cell(x, y) => Some((x, y)),
+            Neighbour::Border(species) => Some((species, u32::MAX)),
+            Neighbour::Missing => None,
+        }
+    }
+
+    #[test]
+    fn boundaries() {
+        let all = [Boundary::Clipped, Boundary::Toroidal, Boundary::Reflective, Boundary::Fixed(2)];
+        // anything on the grid is just itself
+        for boundary in all {
+            assert_eq!(resolve(boundary, 0, 0), Some((0, 0)));
+            assert_eq!(resolve(boundary, 3, 2), Some((3, 2)));
+        }
+        for (x, y) in [(-1, -1), (4, 3), (-1, 1), (2, 3)] {
+            assert_eq!(resolve(Boundary::Clipped, x, y), None);
+            assert_eq!(resolve(Boundary::Fixed(2), x, y), Some((2, u32::MAX)));
+        }
+        // every corner
+        assert_eq!(resolve(Boundary::Toroidal, -1, -1), Some((3, 2)));
+        assert_eq!(resolve(Boundary::Toroidal, 4, -1), Some((0, 2)));
+        assert_eq!(resolve(Boundary::Toroidal, -1, 3), Some((3, 0)));
+        assert_eq!(resolve(Boundary::Toroidal, 4, 3), Some((0, 0)));
+        assert_eq!(resolve(Boundary::Reflective, -1, -1), Some((0, 0)));
+        assert_eq!(resolve(Boundary::Reflective, 4, -1), Some((3, 0)));
+        assert_eq!(resolve(Boundary::Reflective, -1, 3), Some((0, 2)));
+        assert_eq!(resolve(Boundary::Reflective, 4, 3), Some((3, 2)));
+        // further out than one cell
+        assert_eq!(resolve(Boundary::Toroidal, -2, 4), Some((2, 1)));
+        assert_eq!(resolve(Boundary::Reflective, -2, 4), Some((1, 1)));
+        // and further than the whole grid, for big neighbourhoods on small grids
+        // reflecting only folds once, anything still off the grid sticks to the edge
+        assert_eq!(resolve(Boundary::Toroidal, -9, 7), Some((3, 1)));
+        assert_eq!(resolve(Boundary::Reflective, -9, 7), Some((3, 0)));
+        assert_eq!(resolve(Boundary::Reflective, 12, -8), Some((0, 2)));
+    }
+
+    #[test]
+    fn boundary_round_trips() {
+        for boundary in [Boundary::Clipped, Boundary::Toroidal, Boundary::Reflective, Boundary::Fixed(7)] {
+            assert_eq!(boundary.to_string().parse::<Boundary>().unwrap(), boundary);
+        }
+        assert_eq!("wrap".parse::<Boundary>().unwrap(), Boundary::Toroidal);
+        assert_eq!("mirror".parse::<Boundary>().unwrap(), Boundary::Reflective);
+        for bad in ["", "fixed", "fixed:", "fixed:-1", "edge"] {
+            assert!(bad.parse::<Boundary>().is_err(), "{bad}");
+        }
+        let rule = Rule {
+            boundary: Boundary::Fixed(3),
+            ..Rule::default()
+        };
+        assert!(rule.check().is_err(), "border species past the last one");
+    }
+
+    #[test]
+    fn stochastic_odds() {
+        let rule = Rule {
+            takeover: Takeover::Stochastic { probability: 0.5 },
+            ..Rule::default()
+        };
+        let odds = rule.odds();
+        // one for every possible score, from 0 to all 8 neighbours
+        assert_eq!(odds.len(), 9);
+        assert_eq!(odds[0], 0);
+        assert_eq!(odds[1], ODDS_ONE / 2);
+        assert_eq!(odds[2], ODDS_ONE / 4 * 3);
+        assert!(odds.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(Rule::default().odds().iter().all(|&odds| odds == ODDS_ONE));
+        // the same cell and generation always rolls the same, and different ones don't
+        assert_eq!(roll(1234, 56), roll(1234, 56));
+        assert_ne!(roll(1234, 56), roll(1234, 57));
+        assert_ne!(roll(1234, 56), roll(1235, 56));
+        assert!((0..1000).all(|cell| roll(cell, 7) < ODDS_ONE));
+    }
+
+    #[test]
+    fn dominance_parsing() {
+        let Dominance::Matrix { weights } = "0,1,0;0,0,1;1,0,0".parse().unwrap() else {
+            panic!("not a matrix");
+        };
+        assert_eq!(weights, [0, 1, 0, 0, 0, 1, 1, 0, 0]);
+        // newlines work as rows too, and spaces are fine
+        let dominance: Dominance = " 0, 2\n 0, 0 \n".parse().unwrap();
+        assert_eq!(dominance, Dominance::Matrix { weights: vec![0, 2, 0, 0] });
+        assert_eq!(dominance.to_string(), "0,2;0,0");
+        assert_eq!(dominance.species(), Some(2));
+        assert_eq!("cyclic".parse::<Dominance>().unwrap(), Dominance::Cyclic);
+        assert_eq!(Dominance::Cyclic.species(), None);
+        for species in 1..=MAX_SPECIES {
+            let dominance = Dominance::Matrix {
+                weights: vec![1; (species * species) as usize],
+            };
+            assert_eq!(dominance.species(), Some(species));
+            assert_eq!(dominance.to_string().parse::<Dominance>().unwrap(), dominance);
+        }
+        for bad in ["0,1;0", "0,1,0;0,0,1", "0,1;1,0;0,0", "0,-1;1,0", "0,x;1,0", "0,,1;1,0,0;0,1,0"] {
+            assert!(bad.parse::<Dominance>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn dominance_matrices() {
+        // each species is beaten by the next one
+        assert_eq!(Rule::default().dominance_matrix(), [0, 0, 1, 1, 0, 0, 0, 1, 0]);
+        let zeros = Rule {
+            dominance: Dominance::Matrix { weights: vec![0; 9] },
+            ..Rule::default()
+        };
+        zeros.check().unwrap();
+        assert_eq!(zeros.max_score(), 0);
+        let heavy = Rule {
+            dominance: Dominance::Matrix { weights: vec![0, 0, 3, 1, 0, 0, 0, 1, 0] },
+            ..Rule::default()
+        };
+        assert_eq!(heavy.max_score(), 24);
+        // big enough weights would overflow the scores, or need an enormous odds table
+        let mut too_heavy = heavy.clone();
+        too_heavy.dominance = Dominance::Matrix { weights: vec![0, 4_000_000_000, 0, 0, 0, 1, 1, 0, 0] };
+        assert!(too_heavy.check().is_err());
+        too_heavy.dominance = Dominance::Matrix { weights: vec![0, 100_000_000, 0, 0, 0, 1, 1, 0, 0] };
+        assert!(too_heavy.check().is_err());
+        // right up to the cap is fine, moore has 8 neighbours
+        too_heavy.dominance = Dominance::Matrix { weights: vec![0, MAX_SCORE / 8, 0, 0, 0, 1, 1, 0, 0] };
+        too_heavy.check().unwrap();
+        assert_eq!(too_heavy.odds().len(), MAX_SCORE as usize + 1);
+        too_heavy.neighbourhood = Neighbourhood::ExtendedMoore { radius: 2 };
+        assert!(too_heavy.check().is_err());
+        // the matrix has to fit the species count
+        let wrong_size = Rule {
+            species: 4,
+            ..heavy
+        };
+        assert!(wrong_size.check().is_err());
+    }
+
+    #[test]
+    fn neighbourhood_round_trips() {
+        let mut all = vec![Neighbourhood::VonNeumann, Neighbourhood::Moore, Neighbourhood::Hexagonal];
+        all.extend((1..=MAX_RADIUS).map(|radius| Neighbourhood::ExtendedMoore { radius }));
+        for neighbourhood in all {
+            assert_eq!(neighbourhood.to_string().parse::<Neighbourhood>().unwrap(), neighbourhood);
+        }
+        assert_eq!("vn".parse::<Neighbourhood>().unwrap(), Neighbourhood::VonNeumann);
+        assert_eq!("hexagonal".parse::<Neighbourhood>().unwrap(), Neighbourhood::Hexagonal);
+        for bad in ["", "moore:", "moore:0", "moore:9", "moore:-1", "moore:x", "square", "hex:2"] {
+            assert!(bad.parse::<Neighbourhood>().is_err(), "{bad}");
+        }
+    }
+}
@@ -27,78 +27,454 @@ fn vs_main(
 }
 
 @group(0) @binding(2) var<storage, read> colors: array<f32>;
+// keep in sync with Params in simulation.rs
+struct Params {
+    width: u32,
+    height: u32,
+    neighbourhood: u32,
+    radius: u32,
+    threshold: u32,
+    boundary: u32,
+    border_species: u32,
+    species: u32,
+    bits: u32,
+    cells_per_word: u32,
+    row_words: u32,
+    odds_len: u32,
+};
+@group(0) @binding(3) var<uniform> params: Params;
+
+// cells are packed into words, keep in sync with CellLayout in packing.rs
+// which word cell (x, y) is in
+fn word_index(x: u32, y: u32) -> u32 {
+    return y * params.row_words + x / params.cells_per_word;
+}
+
+// pulls cell x out of the word it's in
+fn unpack(word: u32, x: u32) -> u32 {
+    return (word >> ((x % params.cells_per_word) * params.bits)) & ((1u << params.bits) - 1u);
+}
+
+// everything the compute kernels keep track of besides the grid
+// one buffer, since downlevel only allows 4 storage buffers and compute already has the other 3
+// keep in sync with GpuSimulation::tracking_buffer in simulation.rs
+struct Tracking {
+    // how many cells changed in each tick of a batch, only counted when tick.count_changes is set
+    changes: array<atomic<u32>, 64>, // MAX_TICKS_PER_SUBMIT
+    // how many generations since every cell last changed, 8 bits each and stuck at 255
+    // every grid word gets age_words() of these to itself, so no two threads ever write the same one
+    ages: array<u32>,
+};
+@group(0) @binding(7) var<storage, read_write> tracking: Tracking;
+
+// how many u32s of ages each grid word has
+fn age_words() -> u32 {
+    return (params.cells_per_word + 3u) / 4u;
+}
+
+// 0 if cell (x, y) changed in the last tick, up to 255 if it's been that long or longer
+fn cell_age(x: u32, y: u32) -> u32 {
+    let i = x % params.cells_per_word;
+    let ages = tracking.ages[word_index(x, y) * age_words() + i / 4u];
+    return (ages >> ((i % 4u) * 8u)) & 0xffu;
+}
+// keep in sync with View in view.rs
+// where the grid ends up on screen, in pixels
+struct View {
+    offset: vec2<f32>,
+    size: vec2<f32>,
+    age_mode: u32,
+};
+// keep in sync with AgeMode::shader_id in view.rs
+const AGE_OFF: u32 = 0u;
+const AGE_FADE: u32 = 1u;
+const AGE_HEATMAP: u32 = 2u;
+@group(0) @binding(4) var<uniform> view: View;
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // this needs to read the right location in our buffer and display the pixel accordingly
-    let x = in.clip_position.x;
-    let y = in.clip_position.y;
-    let index = u32(x) + u32(y) * 1920;
+    // work in normalized coordinates so the window can be any size
+    let uv = (in.clip_position.xy - view.offset) / view.size;
+    if uv.x < 0.0 || uv.x >= 1.0 || uv.y < 0.0 || uv.y >= 1.0 {
+        // letterboxing
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    let grid_size = vec2<u32>(params.width, params.height);
+    // min() because float rounding can land us right on the far edge
+    let cell = min(vec2<u32>(uv * vec2<f32>(grid_size)), grid_size - 1u);
 
-    let pix = output[index];
+    let pix = unpack(output[word_index(cell.x, cell.y)], cell.x);
     // i tried to pass in a array<array<f32, 3>> but that didn't work
     // so i have to do this cursed thing
     // flat buffers are cooler anyway
     let r = colors[pix*3+0];
     let g = colors[pix*3+1];
     let b = colors[pix*3+2];
-    return vec4<f32>(r,g,b, 1.0);
+    if view.age_mode == AGE_OFF {
+        return vec4<f32>(r,g,b, 1.0);
+    }
+    // 1 for a cell that just changed, heading to 0 for one that's been sitting there a while
+    // a spiral front changes every few generations, so it stays close to 1
+    let fresh = exp(-f32(cell_age(cell.x, cell.y)) / 16.0);
+    if view.age_mode == AGE_FADE {
+        // not all the way to black, so stable regions still show which species they are
+        return vec4<f32>(vec3<f32>(r, g, b) * (0.15 + 0.85 * fresh), 1.0);
+    }
+    // AGE_HEATMAP, black through red and yellow to white
+    let heat = fresh * 3.0;
+    return vec4<f32>(clamp(heat, 0.0, 1.0), clamp(heat - 1.0, 0.0, 1.0), clamp(heat - 2.0, 0.0, 1.0), 1.0);
 }
 
 
+// keep in sync with Neighbourhood::shader_id in rule.rs
+const VON_NEUMANN: u32 = 0u;
+const MOORE: u32 = 1u;
+const EXTENDED_MOORE: u32 = 2u;
+const HEXAGONAL: u32 = 3u;
+
+// keep in sync with Neighbourhood::contains in rule.rs
+fn in_neighbourhood(dx: i32, dy: i32, y: u32) -> bool {
+    if dx == 0 && dy == 0 {
+        return false;
+    }
+    switch params.neighbourhood {
+        case VON_NEUMANN: {
+            return abs(dx) + abs(dy) <= 1;
+        }
+        case HEXAGONAL: {
+            // odd rows are shifted right half a cell
+            if dy == 0 {
+                return true;
+            }
+            if y % 2u == 0u {
+                return dx <= 0;
+            }
+            return dx >= 0;
+        }
+        default: {
+            return true;
+        }
+    }
+}
+
+// keep in sync with Boundary::shader_id in rule.rs
+const CLIPPED: u32 = 0u;
+const TOROIDAL: u32 = 1u;
+const REFLECTIVE: u32 = 2u;
+const FIXED: u32 = 3u;
+// what neighbour_state gives back when there's no neighbour
+const NO_CELL: u32 = 0xffffffffu;
+
+// -1 -> size - 1, size -> 0 and so on
+fn wrap(t: i32, size: i32) -> i32 {
+    if t < 0 {
+        // % on negative numbers isn't the same on every backend, so only ever use it on positive ones
+        return size - 1 - (-t - 1) % size;
+    }
+    return t % size;
+}
+
+// -1 -> 0, -2 -> 1, size -> size - 1 and so on
+fn reflect(t: i32, size: i32) -> i32 {
+    var r = t;
+    if t < 0 {
+        r = -t - 1;
+    } else if t >= size {
+        r = 2 * size - t - 1;
+    }
+    // for neighbourhoods bigger than the grid
+    return clamp(r, 0, size - 1);
+}
+
+// keep in sync with Boundary::resolve in rule.rs
+fn neighbour_state(t_x: i32, t_y: i32) -> u32 {
+    let w = i32(params.width);
+    let h = i32(params.height);
+    var x = t_x;
+    var y = t_y;
+    if x < 0 || x >= w || y < 0 || y >= h {
+        switch params.boundary {
+            case TOROIDAL: {
+                x = wrap(x, w);
+                y = wrap(y, h);
+            }
+            case REFLECTIVE: {
+                x = reflect(x, w);
+                y = reflect(y, h);
+            }
+            case FIXED: {
+                return params.border_species;
+            }
+            default: {
+                return NO_CELL;
+            }
+        }
+    }
+    return read_cell(u32(x), u32(y));
+}
+
+// keep in sync with MAX_SPECIES in rule.rs
+const MAX_SPECIES: u32 = 16u;
+
+// keep in sync with ODDS_ONE in rule.rs
+const ODDS_ONE: u32 = 16777216u; // 1 << 24
+
+// https://www.jcgt.org/published/0009/03/02/
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// keep in sync with roll in rule.rs
+fn roll(cell: u32, generation: u32) -> u32 {
+    return pcg(cell ^ pcg(generation)) >> 8u;
+}
+
+// keep in sync with Tick in simulation.rs
+struct Tick {
+    generation: u32,
+    // which slot of the tick buffer this is, and so which slot of changes it counts into
+    slot: u32,
+    count_changes: u32,
+    track_ages: u32,
+};
+
 @group(0) @binding(0) var<storage, read_write> output: array<u32>;
 @group(0) @binding(1) var<storage, read> input: array<u32>;
-@compute @workgroup_size(16, 9, 1)
-fn compute(
-    @builtin(workgroup_id) workgroup_id : vec3<u32>,
-    @builtin(local_invocation_id) local_invocation_id : vec3<u32>,
-    @builtin(global_invocation_id) global_invocation_id : vec3<u32>,
-    @builtin(local_invocation_index) local_invocation_index: u32,
-    @builtin(num_workgroups) num_workgroups: vec3<u32>
-) {
-    
-   let global_invocation_index = global_invocation_id.y * 1920 + global_invocation_id.x;
-    //output[global_invocation_index] = u32(69);
-    let x = global_invocation_index % 1920;
-    let y = global_invocation_index / 1920;
+// the rule's lookup tables, in one buffer so there's a storage buffer left over for tracking
+// first the chance of getting taken over for every number of winning neighbours, see Rule::odds
+// then the dominance matrix, tables[odds_len + a * species + b] is how hard a beats b, see Dominance::Matrix
+@group(0) @binding(5) var<storage, read> tables: array<u32>;
+@group(0) @binding(6) var<uniform> tick: Tick;
+// keep in sync with WORKGROUP_SIZE in simulation.rs
+const WORKGROUP_WIDTH: u32 = 16u;
+const WORKGROUP_HEIGHT: u32 = 9u;
+// keep in sync with MAX_RADIUS in rule.rs
+const MAX_RADIUS: u32 = 8u;
+// the words this workgroup writes, plus one more word either side and MAX_RADIUS rows above and below
+// a word is at least 8 cells (there's at most 16 species), so one word either side covers any radius
+const TILE_WIDTH: u32 = 18u; // WORKGROUP_WIDTH + 2
+const TILE_HEIGHT: u32 = 25u; // WORKGROUP_HEIGHT + 2 * MAX_RADIUS
+var<workgroup> tile: array<u32, 450>; // TILE_WIDTH * TILE_HEIGHT
+// only compute_tiled fills the tile, compute reads straight from input
+var<private> tiled: bool = false;
+// the word and row that tile[0] is
+var<private> tile_origin: vec2<i32>;
+// rows of the tile that actually got loaded
+var<private> tile_rows: i32;
+
+// (x, y) has to be in the grid
+fn read_cell(x: u32, y: u32) -> u32 {
+    if tiled {
+        let t_x = i32(x / params.cells_per_word) - tile_origin.x;
+        let t_y = i32(y) - tile_origin.y;
+        // wrapped and reflected neighbours can land outside the tile, those go to input like normal
+        if t_x >= 0 && t_x < i32(TILE_WIDTH) && t_y >= 0 && t_y < tile_rows {
+            return unpack(tile[u32(t_y) * TILE_WIDTH + u32(t_x)], x);
+        }
+    }
+    return unpack(input[word_index(x, y)], x);
+}
+
+// works out what cell (x, y) turns into
+fn next_state(x: u32, y: u32) -> u32 {
     // PixelState::Rock => 0,
     // PixelState::Paper => 1,
     // PixelState::Scissors => 2,
-    var wins_against_us = u32(0);
-    let us = input[global_invocation_index];
-    if us == 0 {
-        wins_against_us = u32(1);
-    } else if us == 1 {
-        wins_against_us = u32(2);
-    } else if us == 2 {
-        wins_against_us = u32(0);
-    }
-    //var ou = i32(10) / i32(0);
-    var win_count = u32(0);
-    // check all 8 neighbors (in wgsl)
+    // and so on, who beats who comes from the dominance matrix
+    let us = read_cell(x, y);
+    let species = params.species;
+    // how hard each species beats us
+    var scores: array<u32, MAX_SPECIES>;
+    // check every neighbor (in wgsl)
+    // for moore that's
     // 0 1 2
     // 3 x 4
     // 5 6 7
-    // 0
-    for (var x_o = i32(0); x_o < 3; x_o += 1) {
-        for (var y_o = i32(0); y_o < 3; y_o += 1) {
-            var t_x = i32(x) + x_o - 1;
-            var t_y = i32(y) + y_o - 1;
-            if t_x < 0 || t_x >= 1920 || t_y < 0 || t_y >= 1080 {
+    let r = i32(params.radius);
+    for (var x_o = -r; x_o <= r; x_o += 1) {
+        for (var y_o = -r; y_o <= r; y_o += 1) {
+            if !in_neighbourhood(x_o, y_o, y) {
                 continue;
             }
-            let index = t_x + t_y * 1920;
-            let them = input[index];
-            if them == wins_against_us {
-                win_count += u32(1);
+            let them = neighbour_state(i32(x) + x_o, i32(y) + y_o);
+            if them < species && us < species {
+                scores[them] += tables[params.odds_len + them * species + us];
             }
         }
     }
-    
-    if win_count > 2 {
-        output[global_invocation_index] = wins_against_us;
-    } else {
-        output[global_invocation_index] = us;
+    // whoever beats us the most gets a shot at taking over, ties go to the lower species
+    var wins_against_us = u32(0);
+    var win_count = u32(0);
+    for (var s = u32(0); s < species; s += 1u) {
+        if scores[s] > win_count {
+            wins_against_us = s;
+            win_count = scores[s];
+        }
+    }
+
+    var taken_over = win_count > params.threshold;
+    let chance = tables[min(win_count, params.odds_len - 1u)];
+    // rolling is only needed if it can fail
+    if taken_over && chance < ODDS_ONE {
+        taken_over = roll(y * params.width + x, tick.generation) < chance;
+    }
+    if taken_over {
+        return wins_against_us;
+    }
+    return us;
+}
+
+// works out every cell in word word_x of row y and writes it to the output
+// gives back how many of them changed, if changes are being counted
+fn compute_word(word_x: u32, y: u32) -> u32 {
+    // the grid doesn't have to be a multiple of the workgroup size, so some threads have nothing to do
+    if word_x >= params.row_words || y >= params.height {
+        return 0u;
+    }
+    var word = 0u;
+    for (var i = 0u; i < params.cells_per_word; i += 1u) {
+        let x = word_x * params.cells_per_word + i;
+        // the end of the row is padding, it stays 0
+        if x >= params.width {
+            break;
+        }
+        word |= next_state(x, y) << (i * params.bits);
+    }
+    let index = y * params.row_words + word_x;
+    output[index] = word;
+    if tick.count_changes == 0u && tick.track_ages == 0u {
+        return 0u;
+    }
+    // the padding is 0 in both, so only real cells can be different
+    let diff = word ^ input[index];
+    if tick.track_ages != 0u {
+        update_ages(index, diff);
+    }
+    if tick.count_changes == 0u {
+        return 0u;
+    }
+    var changed = 0u;
+    for (var i = 0u; diff != 0u && i < params.cells_per_word; i += 1u) {
+        if ((diff >> (i * params.bits)) & ((1u << params.bits) - 1u)) != 0u {
+            changed += 1u;
+        }
+    }
+    return changed;
+}
+
+// starts every cell in grid word index that's set in diff back at age 0, and ages the rest by one
+fn update_ages(index: u32, diff: u32) {
+    let first = index * age_words();
+    for (var a = 0u; a < age_words(); a += 1u) {
+        let ages = tracking.ages[first + a];
+        var next = 0u;
+        for (var j = 0u; j < 4u && a * 4u + j < params.cells_per_word; j += 1u) {
+            let i = a * 4u + j;
+            var age = 0u;
+            if ((diff >> (i * params.bits)) & ((1u << params.bits) - 1u)) == 0u {
+                age = min(((ages >> (j * 8u)) & 0xffu) + 1u, 255u);
+            }
+            next |= age << (j * 8u);
+        }
+        tracking.ages[first + a] = next;
+    }
+}
+
+var<workgroup> workgroup_changes: atomic<u32>;
+
+// adds up how many cells changed in the whole workgroup, then adds that to this tick's count
+// every thread in the workgroup has to call this, there's a barrier in it
+fn count_changes(changed: u32, local_invocation_index: u32) {
+    if tick.count_changes == 0u {
+        return;
+    }
+    if changed > 0u {
+        atomicAdd(&workgroup_changes, changed);
     }
-    //output[global_invocation_index] = u32(0);
+    workgroupBarrier();
+    if local_invocation_index == 0u {
+        atomicAdd(&tracking.changes[tick.slot], atomicLoad(&workgroup_changes));
+    }
+}
 
-}
\ No newline at end of file
+// reads every neighbour straight from the input buffer
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn compute(
+    @builtin(workgroup_id) workgroup_id : vec3<u32>,
+    @builtin(local_invocation_id) local_invocation_id : vec3<u32>,
+    @builtin(global_invocation_id) global_invocation_id : vec3<u32>,
+    @builtin(local_invocation_index) local_invocation_index: u32,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    // one thread per word, so no two threads ever write to the same one
+    let changed = compute_word(global_invocation_id.x, global_invocation_id.y);
+    count_changes(changed, local_invocation_index);
+}
+
+// same as compute, but the workgroup copies its bit of the grid into workgroup memory first
+// so each word only gets read from the input once per workgroup instead of once per neighbour
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn compute_tiled(
+    @builtin(workgroup_id) workgroup_id : vec3<u32>,
+    @builtin(global_invocation_id) global_invocation_id : vec3<u32>,
+    @builtin(local_invocation_index) local_invocation_index: u32,
+) {
+    let r = i32(params.radius);
+    tile_origin = vec2<i32>(
+        i32(workgroup_id.x * WORKGROUP_WIDTH) - 1,
+        i32(workgroup_id.y * WORKGROUP_HEIGHT) - r,
+    );
+    tile_rows = min(i32(WORKGROUP_HEIGHT) + 2 * r, i32(TILE_HEIGHT));
+    // every thread loads a few words, only as many rows as the radius needs
+    for (var i = local_invocation_index; i < TILE_WIDTH * u32(tile_rows); i += WORKGROUP_WIDTH * WORKGROUP_HEIGHT) {
+        let word_x = tile_origin.x + i32(i % TILE_WIDTH);
+        let y = tile_origin.y + i32(i / TILE_WIDTH);
+        var word = 0u;
+        // past the edge of the grid is never read from the tile, see read_cell
+        if word_x >= 0 && word_x < i32(params.row_words) && y >= 0 && y < i32(params.height) {
+            word = input[u32(y) * params.row_words + u32(word_x)];
+        }
+        tile[i] = word;
+    }
+    workgroupBarrier();
+    tiled = true;
+    let changed = compute_word(global_invocation_id.x, global_invocation_id.y);
+    count_changes(changed, local_invocation_index);
+}
+
+// how many cells of each species there are, filled in by count
+// then how many cells changed, which StatsCounter::start copies in from tracking.changes afterwards
+@group(0) @binding(8) var<storage, read_write> counts: array<atomic<u32>, 17>; // MAX_SPECIES + 1
+// workgroup memory starts zeroed, so this doesn't need clearing
+var<workgroup> local_counts: array<atomic<u32>, MAX_SPECIES>;
+
+// adds up every species in input, see StatsCounter in stats.rs
+// counting into workgroup memory first means only a handful of atomics per workgroup hit the buffer
+@compute @workgroup_size(WORKGROUP_WIDTH, WORKGROUP_HEIGHT, 1)
+fn count(
+    @builtin(global_invocation_id) global_invocation_id : vec3<u32>,
+    @builtin(local_invocation_index) local_invocation_index: u32,
+) {
+    let word_x = global_invocation_id.x;
+    let y = global_invocation_id.y;
+    // no returning early, every thread has to reach the barrier
+    if word_x < params.row_words && y < params.height {
+        let word = input[y * params.row_words + word_x];
+        for (var i = 0u; i < params.cells_per_word; i += 1u) {
+            let x = word_x * params.cells_per_word + i;
+            if x >= params.width {
+                break;
+            }
+            atomicAdd(&local_counts[unpack(word, x)], 1u);
+        }
+    }
+    workgroupBarrier();
+    if local_invocation_index < MAX_SPECIES {
+        let n = atomicLoad(&local_counts[local_invocation_index]);
+        if n > 0u {
+            atomicAdd(&counts[local_invocation_index], n);
+        }
+    }
+}
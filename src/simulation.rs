@@ -0,0 +1,1325 @@
+use std::{
+    num::NonZeroU64,
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+use anyhow::Context;
+// not std's Instant, that doesn't work on the web
+use game_loop::{Time, TimeTrait};
+use tracing::{info, instrument, warn};
+use wgpu::{include_wgsl, util::DeviceExt};
+
+use crate::{
+    cpu::step_with_rule,
+    history::History,
+    packing::CellLayout,
+    stats::{Stats, StatsCounter},
+    InitialState, Rule,
+};
+
+/// Threads per workgroup in the compute shaders, keep in sync with `WORKGROUP_WIDTH` and `WORKGROUP_HEIGHT`.
+pub(crate) const WORKGROUP_SIZE: (u32, u32) = (16, 9);
+/// Most ticks that get encoded into one submission, each one needs its own slot in the tick buffer
+/// (and in the changes buffer). Keep in sync with the size of `changes` in the shader.
+const MAX_TICKS_PER_SUBMIT: u32 = 64;
+/// The change counts at the start of the tracking buffer, one `u32` per slot. The ages come after,
+/// but only once [`GpuSimulation::set_track_ages`] makes room for them.
+const CHANGES_SIZE: u64 = MAX_TICKS_PER_SUBMIT as u64 * 4;
+
+/// Which compute shader steps the grid. They give exactly the same results, just at different speeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Kernel {
+    /// Every cell reads its neighbours straight from the storage buffer.
+    #[default]
+    Naive,
+    /// Every workgroup copies its tile of the grid (plus a border for the neighbourhood)
+    /// into workgroup memory first, and reads neighbours from there.
+    Tiled,
+}
+
+impl Kernel {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Naive => Self::Tiled,
+            Self::Tiled => Self::Naive,
+        }
+    }
+
+    fn entry_point(self) -> &'static str {
+        match self {
+            Self::Naive => "compute",
+            Self::Tiled => "compute_tiled",
+        }
+    }
+}
+
+impl std::fmt::Display for Kernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Naive => "naive",
+            Self::Tiled => "tiled",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for Kernel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "naive" => Ok(Self::Naive),
+            "tiled" => Ok(Self::Tiled),
+            _ => anyhow::bail!("Unknown kernel: {s} (expected naive or tiled)"),
+        }
+    }
+}
+
+/// A simulation with no window attached.
+///
+/// Runs the compute shader if there's any adapter at all (software ones included) and it gives
+/// us a device, otherwise falls back to [`crate::step_with_rule`] on the cpu.
+pub struct Simulation {
+    backend: Backend,
+    width: u32,
+    height: u32,
+    rule: Rule,
+}
+
+enum Backend {
+    Gpu(Box<GpuSimulation>),
+    Cpu(CpuSimulation),
+}
+
+impl Simulation {
+    /// Creates a `width` x `height` simulation starting from random noise.
+    pub async fn new(width: u32, height: u32, rule: Rule) -> anyhow::Result<Self> {
+        Self::seeded(width, height, rule, rand::random()).await
+    }
+
+    /// Same as [`new`](Self::new), but the noise comes from `seed`, so it's the same on every machine.
+    /// Use [`InitialState::generate`] and [`with_state`](Self::with_state) for other starts.
+    pub async fn seeded(width: u32, height: u32, rule: Rule, seed: u64) -> anyhow::Result<Self> {
+        let state = InitialState::default().generate(width, height, rule.species, seed)?;
+        Self::with_state(state, width, height, rule).await
+    }
+
+    /// Creates a simulation starting from `state`, a row-major `width * height` grid.
+    #[instrument(skip_all)]
+    pub async fn with_state(
+        state: Vec<u32>,
+        width: u32,
+        height: u32,
+        rule: Rule,
+    ) -> anyhow::Result<Self> {
+        rule.check()?;
+        check_state(&state, width, height, rule.species)?;
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let Some(adapter) = adapter else {
+            warn!("No adapter found, falling back to the cpu");
+            return Self::cpu(state, width, height, rule);
+        };
+        info!("Adapter: {:?}", adapter.get_info());
+        let (device, queue) = match request_device(&adapter).await {
+            Ok(device) => device,
+            Err(e) => {
+                warn!("Couldn't get a device ({e:?}), falling back to the cpu");
+                return Self::cpu(state, width, height, rule);
+            }
+        };
+        info!("Compiling Shader");
+        let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
+        let gpu = GpuSimulation::new(
+            Arc::new(device),
+            Arc::new(queue),
+            &shader,
+            &state,
+            width,
+            height,
+            &rule,
+        )?;
+        Ok(Self {
+            backend: Backend::Gpu(Box::new(gpu)),
+            width,
+            height,
+            rule,
+        })
+    }
+
+    /// Creates a simulation that never touches the gpu.
+    pub fn cpu(state: Vec<u32>, width: u32, height: u32, rule: Rule) -> anyhow::Result<Self> {
+        rule.check()?;
+        check_state(&state, width, height, rule.species)?;
+        Ok(Self {
+            backend: Backend::Cpu(CpuSimulation::new(state)),
+            width,
+            height,
+            rule,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// How many generations have been simulated so far.
+    pub fn generation(&self) -> u64 {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.generation,
+            Backend::Cpu(cpu) => cpu.generation,
+        }
+    }
+
+    /// Carries on counting from `generation`, like when picking up from a [`crate::Snapshot`].
+    /// Stochastic rules roll on the generation, so this keeps them playing out the same way.
+    pub fn set_generation(&mut self, generation: u64) {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.generation = generation,
+            Backend::Cpu(cpu) => cpu.generation = generation,
+        }
+    }
+
+    /// Whether the simulation is running on the gpu.
+    pub fn is_gpu(&self) -> bool {
+        matches!(self.backend, Backend::Gpu(_))
+    }
+
+    /// Which compute shader is stepping the grid, `None` on the cpu.
+    pub fn kernel(&self) -> Option<Kernel> {
+        match &self.backend {
+            Backend::Gpu(gpu) => Some(gpu.kernel),
+            Backend::Cpu(_) => None,
+        }
+    }
+
+    /// Switches compute shaders, does nothing on the cpu.
+    pub fn set_kernel(&mut self, kernel: Kernel) {
+        if let Backend::Gpu(gpu) = &mut self.backend {
+            gpu.kernel = kernel;
+        }
+    }
+
+    /// Advances the simulation by `ticks` generations.
+    pub fn advance(&mut self, ticks: u32) {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.advance(ticks),
+            Backend::Cpu(cpu) => {
+                if ticks == 0 {
+                    return;
+                }
+                let start = Time::now();
+                cpu.changes.clear();
+                for _ in 0..ticks {
+                    cpu.tick(self.width, self.height, &self.rule);
+                    if cpu.count_changes {
+                        let changed = cpu.state.iter().zip(&cpu.scratch).filter(|(a, b)| a != b).count();
+                        cpu.changes.push(changed as u64);
+                    }
+                }
+                cpu.last_tick = Some(per_tick(start, ticks));
+            }
+        }
+    }
+
+    /// Counts how many cells change in every tick from now on, see [`changes`](Self::changes).
+    /// [`stats`](Self::stats) only has [`Stats::changed`] while this is on.
+    /// On the gpu that's an atomic in the compute shader plus a readback every batch, so it's off until asked for.
+    pub fn set_count_changes(&mut self, count: bool) {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => {
+                gpu.count_changes = count;
+                gpu.read_changes = count;
+            }
+            Backend::Cpu(cpu) => cpu.count_changes = count,
+        }
+    }
+
+    /// How many cells changed in each tick of the last [`advance`](Self::advance), oldest first.
+    /// Empty unless [`set_count_changes`](Self::set_count_changes) is on.
+    pub fn changes(&self) -> &[u64] {
+        match &self.backend {
+            Backend::Gpu(gpu) => &gpu.changes,
+            Backend::Cpu(cpu) => &cpu.changes,
+        }
+    }
+
+    /// How [`packed_grid`](Self::packed_grid) is laid out.
+    pub fn layout(&self) -> CellLayout {
+        CellLayout::new(self.width, self.height, self.rule.species)
+    }
+
+    /// Counts how many cells of each species there are right now.
+    pub fn stats(&mut self) -> anyhow::Result<Stats> {
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.stats(),
+            Backend::Cpu(cpu) => Ok(Stats {
+                // the same count that advance kept, like on the gpu
+                changed: cpu.last_tick.and(cpu.changes.last().copied()),
+                tick_time: cpu.last_tick,
+                ..Stats::count(&cpu.state, self.rule.species, cpu.generation)
+            }),
+        }
+    }
+
+    /// Reads the current grid back, row-major, one `u32` per cell.
+    pub fn grid(&self) -> anyhow::Result<Vec<u32>> {
+        match &self.backend {
+            Backend::Gpu(gpu) => Ok(gpu.layout.unpack(&gpu.read_back()?)),
+            Backend::Cpu(cpu) => Ok(cpu.state.clone()),
+        }
+    }
+
+    /// Reads the current grid back packed the same way it is on the gpu, see [`CellLayout`].
+    pub fn packed_grid(&self) -> anyhow::Result<Vec<u32>> {
+        match &self.backend {
+            Backend::Gpu(gpu) => gpu.read_back(),
+            Backend::Cpu(cpu) => Ok(self.layout().pack(&cpu.state)),
+        }
+    }
+}
+
+/// Makes sure `state` is a whole `width` x `height` grid of `species` species, anything past the
+/// last species would index past the palette and the counts further down.
+fn check_state(state: &[u32], width: u32, height: u32, species: u32) -> anyhow::Result<()> {
+    anyhow::ensure!(width > 0 && height > 0, "grid can't be empty ({width}x{height})");
+    anyhow::ensure!(
+        state.len() == width as usize * height as usize,
+        "expected {} cells for a {width}x{height} grid, got {}",
+        width as usize * height as usize,
+        state.len()
+    );
+    if let Some(i) = state.iter().position(|&cell| cell >= species) {
+        anyhow::bail!(
+            "cell ({}, {}) is species {}, but there's only {species} species",
+            i % width as usize,
+            i / width as usize,
+            state[i]
+        );
+    }
+    Ok(())
+}
+
+pub(crate) async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: wgpu::Features::empty(),
+                // downlevel's textures stop at 2048, which is too small to capture a 4k grid in,
+                // so those go up to whatever the adapter can do
+                required_limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+                label: None,
+            },
+            None,
+        )
+        .await
+        .context("No device found!")
+}
+
+struct CpuSimulation {
+    state: Vec<u32>,
+    /// The generation before, once there's been a tick.
+    scratch: Vec<u32>,
+    generation: u64,
+    /// How long each tick in the last [`Simulation::advance`] took, `None` before the first.
+    last_tick: Option<Duration>,
+    count_changes: bool,
+    /// See [`Simulation::changes`].
+    changes: Vec<u64>,
+}
+
+impl CpuSimulation {
+    fn new(state: Vec<u32>) -> Self {
+        let scratch = vec![0; state.len()];
+        Self {
+            state,
+            scratch,
+            generation: 0,
+            last_tick: None,
+            count_changes: false,
+            changes: Vec::new(),
+        }
+    }
+    fn tick(&mut self, width: u32, height: u32, rule: &Rule) {
+        step_with_rule(&self.state, &mut self.scratch, width, height, rule, self.generation as u32);
+        std::mem::swap(&mut self.state, &mut self.scratch);
+        self.generation += 1;
+    }
+}
+
+// keep in sync with Params in shader.wgsl
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    width: u32,
+    height: u32,
+    neighbourhood: u32,
+    radius: u32,
+    threshold: u32,
+    boundary: u32,
+    border_species: u32,
+    species: u32,
+    bits: u32,
+    cells_per_word: u32,
+    row_words: u32,
+    /// How much of the tables buffer is odds, the dominance matrix comes after.
+    odds_len: u32,
+}
+
+// keep in sync with Tick in shader.wgsl
+/// The bits of the uniforms that change every tick. Bound with a dynamic offset so a whole
+/// batch of ticks can be written up front and go out in one submission.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Tick {
+    generation: u32,
+    slot: u32,
+    count_changes: u32,
+    track_ages: u32,
+}
+
+/// Everything the compute side needs, shared by [`Simulation`] and the windowed game.
+pub(crate) struct GpuSimulation {
+    /// Shared so the game can build a new simulation on the same device, see `Game::load_snapshot`.
+    pub(crate) device: Arc<wgpu::Device>,
+    pub(crate) queue: Arc<wgpu::Queue>,
+    /// The grid gets ping-ponged between these, each tick reads one and writes the other.
+    pub(crate) data_buffers: [wgpu::Buffer; 2],
+    /// Which of `data_buffers` has the latest generation in it.
+    pub(crate) current: usize,
+    pub(crate) params_buffer: wgpu::Buffer,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// How the cells are packed into `data_buffers`.
+    pub(crate) layout: CellLayout,
+    pub(crate) generation: u64,
+    pub(crate) kernel: Kernel,
+    /// How long each tick in the last [`advance`](Self::advance) took. `None` if the current generation
+    /// didn't come from a tick, which also means the other data buffer isn't the generation before it.
+    last_tick: Option<Duration>,
+    /// Past generations, only kept once [`enable_history`](Self::enable_history) is called.
+    history: Option<History>,
+    tick_buffer: wgpu::Buffer,
+    tick_stride: u64,
+    /// One for each [`Kernel`], in the same order.
+    compute_pipelines: [wgpu::ComputePipeline; 2],
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    /// `compute_bind_groups[i]` reads `data_buffers[i]` and writes the other one.
+    compute_bind_groups: [wgpu::BindGroup; 2],
+    /// The takeover odds then the dominance matrix.
+    tables_buffer: wgpu::Buffer,
+    stats: StatsCounter,
+    /// `Tracking` in the shader: a changed cell count for every slot of the tick buffer, cleared before
+    /// every batch, then how many generations since every cell last changed. There's only room for
+    /// the ages once they've been tracked, and it gets swapped for a bigger buffer then, so anything
+    /// bound to it needs building again after [`set_track_ages`](Self::set_track_ages).
+    pub(crate) tracking_buffer: wgpu::Buffer,
+    /// Whether the compute shader counts changed cells. The counts end up in [`Stats::changed`], and in
+    /// `changes` if `read_changes` is on too, see [`Simulation::set_count_changes`].
+    pub(crate) count_changes: bool,
+    /// Whether [`advance`](Self::advance) reads the change counts back after every batch.
+    read_changes: bool,
+    /// Which slot of the change counts has the tick to the current generation in it, `None` if it
+    /// didn't come from a tick or changes weren't counted.
+    changes_slot: Option<u32>,
+    /// Whether the compute shader keeps the ages up to date, see [`set_track_ages`](Self::set_track_ages).
+    track_ages: bool,
+    /// How many cells changed in each tick of the last [`advance`](Self::advance).
+    pub(crate) changes: Vec<u64>,
+}
+
+impl GpuSimulation {
+    pub(crate) fn new(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        shader: &wgpu::ShaderModule,
+        init_state: &[u32],
+        width: u32,
+        height: u32,
+        rule: &Rule,
+    ) -> anyhow::Result<Self> {
+        rule.check()?;
+        check_state(init_state, width, height, rule.species)?;
+        let layout = CellLayout::new(width, height, rule.species);
+        let init_state = layout.pack(init_state);
+        let data_size = std::mem::size_of_val(init_state.as_slice()) as u64;
+        let max_size = device.limits().max_storage_buffer_binding_size as u64;
+        anyhow::ensure!(
+            data_size <= max_size,
+            "a {width}x{height} grid needs {data_size} bytes, the device only allows {max_size}"
+        );
+        info!("Creating params buffer");
+        let params = Params {
+            width,
+            height,
+            neighbourhood: rule.neighbourhood.shader_id(),
+            radius: rule.neighbourhood.radius(),
+            threshold: rule.threshold(),
+            boundary: rule.boundary.shader_id(),
+            border_species: rule.boundary.border_species(),
+            species: rule.species,
+            bits: layout.bits,
+            cells_per_word: layout.cells_per_word(),
+            row_words: layout.row_words(),
+            odds_len: rule.odds().len() as u32,
+        };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            label: Some("Params Buffer"),
+            contents: bytemuck::bytes_of(&params),
+        });
+        info!("Creating tables buffer");
+        // odds then the dominance matrix, one buffer between them so tracking_buffer fits under
+        // downlevel's limit of 4 storage buffers
+        let mut tables = rule.odds();
+        tables.extend(rule.dominance_matrix());
+        let tables_size = std::mem::size_of_val(tables.as_slice()) as u64;
+        anyhow::ensure!(
+            tables_size <= max_size,
+            "the rule's odds and dominance tables need {tables_size} bytes, the device only allows {max_size}"
+        );
+        let tables_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            usage: wgpu::BufferUsages::STORAGE,
+            label: Some("Tables Buffer"),
+            contents: bytemuck::cast_slice(&tables),
+        });
+        info!("Creating tracking buffer");
+        // the ages array has to have something in it to bind, the real ones wait for set_track_ages
+        let tracking_buffer = create_tracking_buffer(&device, CHANGES_SIZE + 4);
+        info!("Creating tick buffer");
+        let tick_stride = (device.limits().min_uniform_buffer_offset_alignment as u64)
+            .max(std::mem::size_of::<Tick>() as u64);
+        let tick_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            label: Some("Tick Buffer"),
+            size: tick_stride * MAX_TICKS_PER_SUBMIT as u64,
+            mapped_at_creation: false,
+        });
+        info!("Creating data buffers");
+        let usage = wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+        let data_buffers = [
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                usage,
+                label: Some("Data Buffer A"),
+                contents: bytemuck::cast_slice(&init_state),
+            }),
+            // gets written by the first tick, so it doesn't need the initial state
+            device.create_buffer(&wgpu::BufferDescriptor {
+                usage,
+                label: Some("Data Buffer B"),
+                size: data_size,
+                mapped_at_creation: false,
+            }),
+        ];
+        info!("Creating compute pipeline");
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        count: None,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(NonZeroU64::new(4).unwrap()),
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        count: None,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(NonZeroU64::new(4).unwrap()),
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        count: None,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Uniform,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        count: None,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(NonZeroU64::new(4).unwrap()),
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        count: None,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: true,
+                            min_binding_size: NonZeroU64::new(std::mem::size_of::<Tick>() as u64),
+                            ty: wgpu::BufferBindingType::Uniform,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        count: None,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        },
+                    },
+                ],
+            });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Compute Pipeline Layout"),
+            bind_group_layouts: &[&compute_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let compute_pipelines = [Kernel::Naive, Kernel::Tiled].map(|kernel| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Compute Pipeline"),
+                layout: Some(&pipeline_layout),
+                module: shader,
+                entry_point: kernel.entry_point(),
+            })
+        });
+        let compute_bind_groups = create_compute_bind_groups(
+            &device,
+            &compute_bind_group_layout,
+            &data_buffers,
+            &params_buffer,
+            &tables_buffer,
+            &tick_buffer,
+            &tracking_buffer,
+        );
+        let stats = StatsCounter::new(
+            &device,
+            shader,
+            &data_buffers,
+            &params_buffer,
+            layout,
+            rule.species,
+        );
+        Ok(Self {
+            device,
+            queue,
+            data_buffers,
+            current: 0,
+            params_buffer,
+            width,
+            height,
+            layout,
+            generation: 0,
+            kernel: Kernel::default(),
+            last_tick: None,
+            history: None,
+            tick_buffer,
+            tick_stride,
+            compute_pipelines,
+            compute_bind_group_layout,
+            compute_bind_groups,
+            tables_buffer,
+            stats,
+            tracking_buffer,
+            count_changes: false,
+            read_changes: false,
+            changes_slot: None,
+            track_ages: false,
+            changes: Vec::new(),
+        })
+    }
+
+    /// The buffer with the latest generation in it.
+    pub(crate) fn current_buffer(&self) -> &wgpu::Buffer {
+        &self.data_buffers[self.current]
+    }
+
+    /// Starts over from `state`, one `u32` per cell, at generation 0.
+    /// Forgets the history, it was for the old grid.
+    pub(crate) fn write_state(&mut self, state: &[u32]) {
+        self.queue
+            .write_buffer(self.current_buffer(), 0, bytemuck::cast_slice(&self.layout.pack(state)));
+        // stochastic rules roll on the generation, so this makes a reset play out the same way again
+        self.generation = 0;
+        self.last_tick = None;
+        self.changes_slot = None;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        if self.track_ages {
+            self.forget_ages();
+        }
+    }
+
+    /// Starts or stops keeping track of how long it's been since every cell changed, for drawing
+    /// with `AgeMode`. They're only right for the ticks since it was turned on, so they all start
+    /// out as old as they go, and anything changing from then on stands out.
+    /// The first time it's turned on, this makes room for them (about a byte per cell), which fails
+    /// if the device doesn't allow a buffer that big.
+    pub(crate) fn set_track_ages(&mut self, track: bool) -> anyhow::Result<()> {
+        if track && !self.track_ages {
+            // a byte per cell, but every grid word gets whole u32s of them to itself, see age_words in the shader
+            let ages_size = self.current_buffer().size() * self.layout.cells_per_word().div_ceil(4) as u64;
+            let size = CHANGES_SIZE + ages_size;
+            if self.tracking_buffer.size() < size {
+                let max_size = self.device.limits().max_storage_buffer_binding_size as u64;
+                anyhow::ensure!(
+                    size <= max_size,
+                    "a {}x{} grid needs {size} bytes of cell ages, the device only allows {max_size}",
+                    self.width,
+                    self.height
+                );
+                info!("Creating tracking buffer with room for ages");
+                self.tracking_buffer = create_tracking_buffer(&self.device, size);
+                self.compute_bind_groups = create_compute_bind_groups(
+                    &self.device,
+                    &self.compute_bind_group_layout,
+                    &self.data_buffers,
+                    &self.params_buffer,
+                    &self.tables_buffer,
+                    &self.tick_buffer,
+                    &self.tracking_buffer,
+                );
+                // the count for this generation stayed in the old one
+                self.changes_slot = None;
+            }
+            self.forget_ages();
+        }
+        self.track_ages = track;
+        Ok(())
+    }
+
+    fn forget_ages(&self) {
+        let ages = vec![0xff; (self.tracking_buffer.size() - CHANGES_SIZE) as usize];
+        self.queue.write_buffer(&self.tracking_buffer, CHANGES_SIZE, &ages);
+    }
+
+    /// Starts keeping up to `budget` bytes of past generations so [`step_back`](Self::step_back) works.
+    pub(crate) fn enable_history(&mut self, budget: u64) {
+        self.history = Some(History::new(&self.device, self.current_buffer().size(), budget));
+    }
+
+    /// Goes back a generation, false if there's no history left (or it isn't enabled).
+    pub(crate) fn step_back(&mut self) -> bool {
+        let Some(history) = &mut self.history else {
+            return false;
+        };
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Step Back Encoder"),
+            });
+        if !history.pop(&mut encoder, &self.data_buffers[self.current]) {
+            return false;
+        }
+        self.queue.submit(Some(encoder.finish()));
+        self.generation -= 1;
+        self.last_tick = None;
+        self.changes_slot = None;
+        // the ages don't get kept with the history, and they'd be wrong for the generation before
+        if self.track_ages {
+            self.forget_ages();
+        }
+        true
+    }
+
+    /// `slot` is which slot of the tick buffer this tick's uniforms were written to.
+    fn encode_tick(&mut self, encoder: &mut wgpu::CommandEncoder, slot: u32) {
+        if let Some(history) = &mut self.history {
+            history.push(encoder, &self.data_buffers[self.current]);
+        }
+        let bind_group = &self.compute_bind_groups[self.current];
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute"),
+            timestamp_writes: None,
+        });
+        cpass.set_bind_group(0, bind_group, &[(slot as u64 * self.tick_stride) as u32]);
+        cpass.set_pipeline(&self.compute_pipelines[self.kernel as usize]);
+        // one thread per word, rounded up to whole workgroups
+        cpass.dispatch_workgroups(
+            self.layout.row_words().div_ceil(WORKGROUP_SIZE.0),
+            self.height.div_ceil(WORKGROUP_SIZE.1),
+            1,
+        );
+        drop(cpass);
+        self.current = 1 - self.current;
+    }
+
+    pub(crate) fn advance(&mut self, ticks: u32) {
+        if ticks == 0 {
+            return;
+        }
+        let start = Time::now();
+        let mut remaining = ticks;
+        let mut last = None;
+        self.changes.clear();
+        while remaining > 0 {
+            let batch = remaining.min(MAX_TICKS_PER_SUBMIT);
+            // write_buffer lands before the submit below, so every tick in the batch gets its own generation
+            let mut ticks = vec![0u8; (self.tick_stride * batch as u64) as usize];
+            for slot in 0..batch {
+                let tick = Tick {
+                    generation: (self.generation + slot as u64) as u32,
+                    slot,
+                    count_changes: self.count_changes.into(),
+                    track_ages: self.track_ages.into(),
+                };
+                let start = (slot as u64 * self.tick_stride) as usize;
+                ticks[start..start + std::mem::size_of::<Tick>()].copy_from_slice(bytemuck::bytes_of(&tick));
+            }
+            self.queue.write_buffer(&self.tick_buffer, 0, &ticks);
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Command Encoder"),
+                });
+            if self.count_changes {
+                encoder.clear_buffer(&self.tracking_buffer, 0, Some(CHANGES_SIZE));
+            }
+            for slot in 0..batch {
+                self.encode_tick(&mut encoder, slot);
+            }
+            last = Some(self.queue.submit(Some(encoder.finish())));
+            self.generation += batch as u64;
+            remaining -= batch;
+            self.changes_slot = self.count_changes.then_some(batch - 1);
+            if self.read_changes {
+                // has to be read before the next batch clears it, so this waits for every batch
+                match self.read_buffer(&self.tracking_buffer, CHANGES_SIZE) {
+                    Ok(changes) => self
+                        .changes
+                        .extend(changes[..batch as usize].iter().map(|&c| c as u64)),
+                    Err(e) => warn!("Couldn't read back changed cells: {:?}", e),
+                }
+            }
+        }
+        if let Some(ind) = last {
+            self.device
+                .poll(wgpu::MaintainBase::WaitForSubmissionIndex(ind));
+        }
+        // on the web that poll doesn't wait, so this is only how long it took to send the work off
+        self.last_tick = Some(per_tick(start, ticks));
+    }
+
+    /// Starts counting the species in the current generation, false if the last count isn't back yet.
+    pub(crate) fn request_stats(&mut self) -> bool {
+        self.stats.start(
+            &self.device,
+            &self.queue,
+            self.current,
+            self.generation,
+            self.last_tick,
+            self.changes_slot.map(|slot| (&self.tracking_buffer, slot)),
+        )
+    }
+
+    /// The last requested count, if it's back yet. Never blocks.
+    pub(crate) fn poll_stats(&mut self) -> Option<anyhow::Result<Stats>> {
+        self.stats.try_finish(&self.device)
+    }
+
+    /// Counts the species in the current generation, waiting for it.
+    pub(crate) fn stats(&mut self) -> anyhow::Result<Stats> {
+        // anything already on its way back is an older generation
+        if let Some(Err(e)) = self.stats.wait(&self.device) {
+            return Err(e);
+        }
+        self.request_stats();
+        self.stats.wait(&self.device).context("Count never started")?
+    }
+
+    /// Reads the current grid back, still packed.
+    pub(crate) fn read_back(&self) -> anyhow::Result<Vec<u32>> {
+        self.read_buffer(self.current_buffer(), self.current_buffer().size())
+    }
+
+    /// Copies the first `size` bytes of `buffer` somewhere mappable and waits for them.
+    fn read_buffer(&self, buffer: &wgpu::Buffer, size: u64) -> anyhow::Result<Vec<u32>> {
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, staging.size());
+        let ind = self.queue.submit(Some(encoder.finish()));
+        let slice = staging.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |res| {
+            let _ = tx.send(res);
+        });
+        self.device
+            .poll(wgpu::MaintainBase::WaitForSubmissionIndex(ind));
+        rx.recv()
+            .context("Readback was never mapped")?
+            .context("Failed to map readback buffer")?;
+        let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging.unmap();
+        Ok(data)
+    }
+}
+
+/// How long each of `ticks` ticks took, if they all started at `start`.
+fn per_tick(start: Time, ticks: u32) -> Duration {
+    Duration::from_secs_f64(Time::now().sub(&start) / ticks as f64)
+}
+
+fn create_tracking_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        label: Some("Tracking Buffer"),
+        size,
+        mapped_at_creation: false,
+    })
+}
+
+/// Index `i` reads `data_buffers[i]` and writes the other one.
+fn create_compute_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    data_buffers: &[wgpu::Buffer; 2],
+    params_buffer: &wgpu::Buffer,
+    tables_buffer: &wgpu::Buffer,
+    tick_buffer: &wgpu::Buffer,
+    tracking_buffer: &wgpu::Buffer,
+) -> [wgpu::BindGroup; 2] {
+    [0, 1].map(|input| {
+        let output = 1 - input;
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Compute Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data_buffers[output].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: data_buffers[input].as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: tables_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: tick_buffer,
+                        offset: 0,
+                        size: NonZeroU64::new(std::mem::size_of::<Tick>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: tracking_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+
+    use crate::{Boundary, Dominance, Neighbourhood, Takeover, MAX_RADIUS, MAX_SPECIES};
+
+    /// How many generations every gpu run gets checked for.
+    const GENERATIONS: u32 = 8;
+
+    /// One device shared by every test, some drivers (like mesa's gl) fall over with more than one
+    /// instance alive at a time. `None` if there's nothing to run on.
+    fn device() -> Option<&'static (Arc<wgpu::Device>, Arc<wgpu::Queue>)> {
+        static DEVICE: OnceLock<Option<(Arc<wgpu::Device>, Arc<wgpu::Queue>)>> = OnceLock::new();
+        DEVICE
+            .get_or_init(|| {
+                let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+                    backends: wgpu::Backends::all(),
+                    ..Default::default()
+                });
+                let adapter = [false, true].into_iter().find_map(|force_fallback_adapter| {
+                    smol::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference: wgpu::PowerPreference::default(),
+                        compatible_surface: None,
+                        force_fallback_adapter,
+                    }))
+                })?;
+                let (device, queue) = smol::block_on(request_device(&adapter)).ok()?;
+                Some((Arc::new(device), Arc::new(queue)))
+            })
+            .as_ref()
+    }
+
+    /// A seeded `width` x `height` simulation on the gpu, `None` if there's nothing to run it on.
+    fn gpu(width: u32, height: u32, rule: &Rule, seed: u64) -> Option<Simulation> {
+        let Some((device, queue)) = device() else {
+            eprintln!("no adapter, skipping the gpu side");
+            return None;
+        };
+        let state = InitialState::default()
+            .generate(width, height, rule.species, seed)
+            .unwrap();
+        let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
+        let gpu = GpuSimulation::new(
+            device.clone(),
+            queue.clone(),
+            &shader,
+            &state,
+            width,
+            height,
+            rule,
+        )
+        .unwrap();
+        Some(Simulation {
+            backend: Backend::Gpu(Box::new(gpu)),
+            width,
+            height,
+            rule: rule.clone(),
+        })
+    }
+
+    /// Steps the same seeded grid on the gpu and the cpu, checking they agree after every generation.
+    fn assert_gpu_matches_cpu(width: u32, height: u32, rule: &Rule, seed: u64, kernel: Kernel) {
+        let Some(mut gpu) = gpu(width, height, rule, seed) else {
+            return;
+        };
+        gpu.set_kernel(kernel);
+        let mut cpu = Simulation::cpu(gpu.grid().unwrap(), width, height, rule.clone()).unwrap();
+        for _ in 0..GENERATIONS {
+            gpu.advance(1);
+            cpu.advance(1);
+            assert_eq!(
+                gpu.grid().unwrap(),
+                cpu.grid().unwrap(),
+                "{width}x{height} {rule}, seed {seed}, {kernel} kernel, generation {}",
+                cpu.generation()
+            );
+        }
+    }
+
+    #[test]
+    fn gpu_matches_cpu() {
+        // small, odd sizes so rows don't fill whole words or whole workgroups
+        for (width, height, seed) in [(5, 5, 1), (37, 23, 2), (64, 9, 3), (100, 31, 4)] {
+            assert_gpu_matches_cpu(width, height, &Rule::default(), seed, Kernel::Naive);
+        }
+    }
+
+    #[test]
+    fn gpu_matches_cpu_for_every_neighbourhood() {
+        let mut neighbourhoods = vec![Neighbourhood::VonNeumann, Neighbourhood::Moore, Neighbourhood::Hexagonal];
+        neighbourhoods.extend([1, 2, 3, MAX_RADIUS].map(|radius| Neighbourhood::ExtendedMoore { radius }));
+        for (seed, neighbourhood) in neighbourhoods.into_iter().enumerate() {
+            let rule = Rule {
+                neighbourhood,
+                ..Rule::default()
+            };
+            assert_gpu_matches_cpu(41, 27, &rule, seed as u64, Kernel::Naive);
+        }
+    }
+
+    #[test]
+    fn gpu_matches_cpu_for_every_boundary() {
+        let boundaries = [Boundary::Clipped, Boundary::Toroidal, Boundary::Reflective, Boundary::Fixed(1)];
+        for (seed, boundary) in boundaries.into_iter().enumerate() {
+            for neighbourhood in [Neighbourhood::Moore, Neighbourhood::Hexagonal] {
+                let rule = Rule {
+                    boundary,
+                    neighbourhood,
+                    ..Rule::default()
+                };
+                assert_gpu_matches_cpu(35, 19, &rule, seed as u64, Kernel::Naive);
+            }
+            // reaching further than the grid is across, so neighbours wrap or reflect more than once
+            let rule = Rule {
+                boundary,
+                neighbourhood: Neighbourhood::ExtendedMoore { radius: 4 },
+                ..Rule::default()
+            };
+            assert_gpu_matches_cpu(5, 3, &rule, seed as u64, Kernel::Naive);
+        }
+    }
+
+    #[test]
+    fn gpu_matches_cpu_with_stochastic_takeovers() {
+        let rule = Rule {
+            takeover: Takeover::Stochastic { probability: 0.5 },
+            ..Rule::default()
+        };
+        for seed in 0..3 {
+            assert_gpu_matches_cpu(47, 33, &rule, seed, Kernel::Naive);
+        }
+        // the rolls are seeded by the generation, so carrying on from a snapshot has to agree too
+        let Some(mut gpu) = gpu(47, 33, &rule, 3) else {
+            return;
+        };
+        let mut cpu = Simulation::cpu(gpu.grid().unwrap(), 47, 33, rule).unwrap();
+        gpu.set_generation(123_456);
+        cpu.set_generation(123_456);
+        gpu.advance(GENERATIONS);
+        cpu.advance(GENERATIONS);
+        assert_eq!(gpu.grid().unwrap(), cpu.grid().unwrap());
+    }
+
+    #[test]
+    fn gpu_matches_cpu_with_dominance_matrices() {
+        let matrices = [
+            // rock, paper, scissors, lizard, spock
+            "0,1,0,0,1;0,0,1,1,0;1,0,0,0,1;1,0,1,0,0;0,1,0,1,0",
+            // uneven weights, and a species that never loses
+            "0,2,0;0,0,3;1,0,0",
+            "0,0,0,0;1,0,0,0;1,2,0,0;1,1,1,0",
+        ];
+        for (seed, matrix) in matrices.into_iter().enumerate() {
+            let dominance: Dominance = matrix.parse().unwrap();
+            let rule = Rule {
+                species: dominance.species().unwrap(),
+                dominance,
+                ..Rule::default()
+            };
+            assert_gpu_matches_cpu(39, 21, &rule, seed as u64, Kernel::Naive);
+        }
+        // with more species than fit in 2 bits, and a radius so scores go past 8
+        let rule = Rule {
+            species: 7,
+            dominance: Dominance::Matrix {
+                weights: (0..49).map(|i| (i * 5 % 7) % 3).collect(),
+            },
+            neighbourhood: Neighbourhood::ExtendedMoore { radius: 2 },
+            threshold: Some(10),
+            ..Rule::default()
+        };
+        assert_gpu_matches_cpu(39, 21, &rule, 3, Kernel::Naive);
+    }
+
+    #[test]
+    fn tiled_matches_naive() {
+        let boundaries = [Boundary::Clipped, Boundary::Toroidal, Boundary::Reflective, Boundary::Fixed(2)];
+        let neighbourhoods = [1, 3, MAX_RADIUS]
+            .map(|radius| Neighbourhood::ExtendedMoore { radius })
+            .into_iter()
+            .chain([Neighbourhood::Hexagonal]);
+        for (seed, (boundary, neighbourhood)) in boundaries
+            .into_iter()
+            .flat_map(|boundary| neighbourhoods.clone().map(move |n| (boundary, n)))
+            .enumerate()
+        {
+            // 5 species is 8 cells a word, so this is a few workgroups each way, and the last ones are partial
+            let rule = Rule {
+                species: 5,
+                boundary,
+                neighbourhood,
+                ..Rule::default()
+            };
+            let (width, height) = (300, 40);
+            let (Some(mut naive), Some(mut tiled)) = (
+                gpu(width, height, &rule, seed as u64),
+                gpu(width, height, &rule, seed as u64),
+            ) else {
+                return;
+            };
+            naive.set_kernel(Kernel::Naive);
+            tiled.set_kernel(Kernel::Tiled);
+            for generation in 1..=GENERATIONS {
+                naive.advance(1);
+                tiled.advance(1);
+                assert_eq!(
+                    naive.packed_grid().unwrap(),
+                    tiled.packed_grid().unwrap(),
+                    "{rule}, seed {seed}, generation {generation}"
+                );
+            }
+        }
+        assert_gpu_matches_cpu(300, 40, &Rule::default(), 0, Kernel::Tiled);
+    }
+
+    #[test]
+    fn gpu_matches_cpu_in_one_batch() {
+        let rule = Rule::default();
+        let Some(mut gpu) = gpu(53, 29, &rule, 5) else {
+            return;
+        };
+        let mut cpu = Simulation::cpu(gpu.grid().unwrap(), 53, 29, rule).unwrap();
+        // more than fit in one submission
+        gpu.advance(MAX_TICKS_PER_SUBMIT + 3);
+        cpu.advance(MAX_TICKS_PER_SUBMIT + 3);
+        assert_eq!(gpu.generation(), cpu.generation());
+        assert_eq!(gpu.grid().unwrap(), cpu.grid().unwrap());
+    }
+
+    #[test]
+    fn stats_match_cpu() {
+        let rule = Rule::default();
+        let Some(mut gpu) = gpu(45, 31, &rule, 8) else {
+            return;
+        };
+        let mut cpu = Simulation::cpu(gpu.grid().unwrap(), 45, 31, rule).unwrap();
+        for sim in [&mut gpu, &mut cpu] {
+            // nothing's been ticked to yet
+            let stats = sim.stats().unwrap();
+            assert_eq!((stats.changed, stats.tick_time), (None, None));
+            assert_eq!(stats.total(), 45 * 31);
+            sim.set_count_changes(true);
+        }
+        // the changed cells are the last tick's, however many ticks there were in between
+        for ticks in [1, 5, MAX_TICKS_PER_SUBMIT + 2] {
+            gpu.advance(ticks);
+            cpu.advance(ticks);
+            let (gpu_stats, cpu_stats) = (gpu.stats().unwrap(), cpu.stats().unwrap());
+            assert_eq!(gpu_stats.counts, cpu_stats.counts);
+            assert_eq!(gpu_stats.changed, cpu_stats.changed);
+            assert_eq!(gpu_stats.changed, gpu.changes().last().copied());
+            assert_eq!(gpu.changes(), cpu.changes());
+            assert_eq!(gpu.changes().len(), ticks as usize);
+        }
+        // nothing to go on without counting
+        for sim in [&mut gpu, &mut cpu] {
+            sim.set_count_changes(false);
+            sim.advance(1);
+            assert_eq!(sim.stats().unwrap().changed, None);
+            assert!(sim.changes().is_empty());
+        }
+    }
+
+    #[test]
+    fn ages_only_get_room_once_tracked() {
+        let Some(Simulation {
+            backend: Backend::Gpu(mut gpu),
+            ..
+        }) = gpu(45, 31, &Rule::default(), 5)
+        else {
+            return;
+        };
+        let ages = |gpu: &GpuSimulation| {
+            let tracking = gpu.read_buffer(&gpu.tracking_buffer, gpu.tracking_buffer.size()).unwrap();
+            tracking[CHANGES_SIZE as usize / 4..].to_vec()
+        };
+        let all_old = |ages: Vec<u32>| ages.iter().all(|&age| age == u32::MAX);
+        assert_eq!(gpu.tracking_buffer.size(), CHANGES_SIZE + 4);
+        gpu.enable_history(1 << 20);
+        gpu.advance(2);
+        gpu.set_track_ages(true).unwrap();
+        // 45 cells a row is 3 words of 16, each with 4 u32s of ages
+        assert_eq!(ages(&gpu).len(), 31 * 3 * 4);
+        assert!(all_old(ages(&gpu)));
+        gpu.advance(3);
+        assert!(!all_old(ages(&gpu)));
+        // they'd be wrong for the generation before, so they start over
+        assert!(gpu.step_back());
+        assert!(all_old(ages(&gpu)));
+        gpu.advance(3);
+        let state = InitialState::default().generate(45, 31, 3, 5).unwrap();
+        gpu.write_state(&state);
+        assert!(all_old(ages(&gpu)));
+        // the room stays once it's there
+        gpu.set_track_ages(false).unwrap();
+        gpu.set_track_ages(true).unwrap();
+        assert_eq!(gpu.tracking_buffer.size(), CHANGES_SIZE + 31 * 3 * 4 * 4);
+    }
+
+    #[test]
+    fn steps_back_through_the_history() {
+        let Some(Simulation {
+            backend: Backend::Gpu(mut gpu),
+            ..
+        }) = gpu(45, 31, &Rule::default(), 6)
+        else {
+            return;
+        };
+        // room for 3 generations
+        gpu.enable_history(gpu.current_buffer().size() * 3);
+        let mut grids = vec![gpu.read_back().unwrap()];
+        for _ in 0..5 {
+            gpu.advance(1);
+            grids.push(gpu.read_back().unwrap());
+        }
+        // the oldest ones got written over
+        for generation in (2..5).rev() {
+            assert!(gpu.step_back());
+            assert_eq!(gpu.generation, generation);
+            assert_eq!(gpu.read_back().unwrap(), grids[generation as usize]);
+        }
+        assert!(!gpu.step_back());
+        assert_eq!(gpu.generation, 2);
+    }
+
+    #[test]
+    fn cpu_matches_step() {
+        let rule = Rule::default();
+        let state = InitialState::default().generate(20, 10, 3, 6).unwrap();
+        let mut sim = Simulation::cpu(state.clone(), 20, 10, rule).unwrap();
+        sim.advance(1);
+        let mut next = vec![0; state.len()];
+        crate::step(&state, &mut next, 20, 10);
+        assert_eq!(sim.grid().unwrap(), next);
+    }
+
+    #[test]
+    fn rejects_bad_states() {
+        let rule = Rule::default();
+        assert!(Simulation::cpu(vec![0; 12], 4, 3, rule.clone()).is_ok());
+        assert!(Simulation::cpu(vec![0; 11], 4, 3, rule.clone()).is_err());
+        assert!(Simulation::cpu(vec![], 0, 3, rule.clone()).is_err());
+        let mut state = vec![2; 12];
+        state[6] = 3;
+        let error = Simulation::cpu(state.clone(), 4, 3, rule.clone()).err().unwrap();
+        assert!(error.to_string().contains("cell (2, 1) is species 3"), "{error}");
+        assert!(smol::block_on(Simulation::with_state(state, 4, 3, rule)).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "bad rule")]
+    fn step_rejects_bad_rule() {
+        let rule = Rule {
+            species: MAX_SPECIES + 1,
+            ..Rule::default()
+        };
+        let state = vec![0; 16];
+        step_with_rule(&state, &mut [0; 16], 4, 4, &rule, 0);
+    }
+}
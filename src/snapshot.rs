@@ -0,0 +1,264 @@
+use std::io::{Read, Write};
+
+use anyhow::Context;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{config::check_size, packing::CellLayout, InitialState, Rule, Takeover};
+
+const MAGIC: &[u8; 8] = b"OWMYEYES";
+/// Bump this whenever the layout below changes, and keep reading the old ones if it's easy.
+const VERSION: u32 = 1;
+
+/// Everything needed to pick a run back up exactly where it was.
+///
+/// Version 1 is, all little endian, strings as a `u32` byte length then utf-8:
+/// - `OWMYEYES`, then the version as a `u32`
+/// - width and height as `u32`s
+/// - the rule: species `u32`, then neighbourhood, boundary and dominance as strings (the same as
+///   their command line options), then the threshold (`u8` 1 and a `u32`, or `u8` 0 for the default),
+///   then the takeover (`u8` 0 for deterministic, or `u8` 1 and an `f64` probability)
+/// - the seed and generation as `u64`s, and the initial state as a string
+/// - the cells packed like [`CellLayout`], zlib compressed, as a `u32` length then the bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub width: u32,
+    pub height: u32,
+    pub rule: Rule,
+    pub seed: u64,
+    pub init: InitialState,
+    pub generation: u64,
+    /// Row-major, one `u32` per cell.
+    pub cells: Vec<u32>,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        put_u32(&mut out, VERSION);
+        put_u32(&mut out, self.width);
+        put_u32(&mut out, self.height);
+        put_u32(&mut out, self.rule.species);
+        put_str(&mut out, &self.rule.neighbourhood.to_string());
+        put_str(&mut out, &self.rule.boundary.to_string());
+        put_str(&mut out, &self.rule.dominance.to_string());
+        match self.rule.threshold {
+            Some(threshold) => {
+                out.push(1);
+                put_u32(&mut out, threshold);
+            }
+            None => out.push(0),
+        }
+        match self.rule.takeover {
+            Takeover::Deterministic => out.push(0),
+            Takeover::Stochastic { probability } => {
+                out.push(1);
+                out.extend_from_slice(&probability.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.generation.to_le_bytes());
+        put_str(&mut out, &self.init.to_string());
+        let words = CellLayout::new(self.width, self.height, self.rule.species).pack(&self.cells);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        // writing to a vec can't fail
+        encoder.write_all(&bytes).expect("writing to a vec");
+        let compressed = encoder.finish().expect("writing to a vec");
+        put_u32(&mut out, compressed.len() as u32);
+        out.extend_from_slice(&compressed);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(bytes.starts_with(MAGIC), "Not a snapshot");
+        let mut r = Reader(&bytes[MAGIC.len()..]);
+        let version = r.u32()?;
+        anyhow::ensure!(
+            version == VERSION,
+            "Snapshot is version {version}, this only reads version {VERSION}"
+        );
+        let width = r.u32()?;
+        let height = r.u32()?;
+        // an empty row would panic the unpacking, and a huge one would never fit anyway
+        check_size(width, height).context("Snapshot has a bad size")?;
+        let rule = Rule {
+            species: r.u32()?,
+            neighbourhood: r.str()?.parse()?,
+            boundary: r.str()?.parse()?,
+            dominance: r.str()?.parse()?,
+            threshold: match r.u8()? {
+                0 => None,
+                _ => Some(r.u32()?),
+            },
+            takeover: match r.u8()? {
+                0 => Takeover::Deterministic,
+                _ => Takeover::Stochastic {
+                    probability: f64::from_le_bytes(r.array()?),
+                },
+            },
+        };
+        rule.check().context("Snapshot has a bad rule")?;
+        let seed = u64::from_le_bytes(r.array()?);
+        let generation = u64::from_le_bytes(r.array()?);
+        let init = r.str()?.parse()?;
+        let compressed_len = r.u32()? as usize;
+        let mut packed = Vec::new();
+        ZlibDecoder::new(r.take(compressed_len)?)
+            .read_to_end(&mut packed)
+            .context("Snapshot cells are corrupt")?;
+        let layout = CellLayout::new(width, height, rule.species);
+        anyhow::ensure!(
+            packed.len() == layout.words() * 4,
+            "Snapshot should have {} bytes of cells for a {width}x{height} grid, got {}",
+            layout.words() * 4,
+            packed.len()
+        );
+        let words: Vec<u32> = packed
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().expect("chunks of 4")))
+            .collect();
+        let cells = layout.unpack(&words);
+        // the packing has room for more species than there are, and those would index past the palette
+        if let Some(i) = cells.iter().position(|&cell| cell >= rule.species) {
+            anyhow::bail!(
+                "Snapshot has species {} at ({}, {}), but there's only {} species",
+                cells[i],
+                i % width as usize,
+                i / width as usize,
+                rule.species
+            );
+        }
+        Ok(Self {
+            width,
+            height,
+            rule,
+            seed,
+            init,
+            generation,
+            cells,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes())
+            .with_context(|| format!("Couldn't write {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+        Self::from_bytes(&bytes).with_context(|| format!("Couldn't load {}", path.display()))
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Reads bits off the front of a snapshot.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        anyhow::ensure!(self.0.len() >= n, "Snapshot is cut short");
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn str(&mut self) -> anyhow::Result<&'a str> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).context("Snapshot has a bad string")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Boundary, Neighbourhood};
+
+    fn snapshot() -> Snapshot {
+        let rule = Rule {
+            species: 5,
+            neighbourhood: Neighbourhood::ExtendedMoore { radius: 2 },
+            boundary: Boundary::Fixed(4),
+            dominance: "0,1,0,0,1;0,0,1,1,0;1,0,0,0,1;1,0,1,0,0;0,1,0,1,0".parse().unwrap(),
+            threshold: Some(7),
+            takeover: Takeover::Stochastic { probability: 0.25 },
+        };
+        let init = InitialState::Voronoi { seeds: 12 };
+        Snapshot {
+            width: 13,
+            height: 7,
+            cells: init.generate(13, 7, rule.species, 99).unwrap(),
+            rule,
+            seed: 99,
+            init,
+            generation: 1234,
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let snapshot = snapshot();
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+        let plain = Snapshot {
+            rule: Rule::default(),
+            init: InitialState::default(),
+            cells: InitialState::default().generate(13, 7, 3, 5).unwrap(),
+            ..snapshot
+        };
+        assert_eq!(Snapshot::from_bytes(&plain.to_bytes()).unwrap(), plain);
+    }
+
+    #[test]
+    fn rejects_bad_cells() {
+        // 5 species get 4 bits, which has room for up to 15
+        let mut snapshot = snapshot();
+        snapshot.cells[20] = 5;
+        let error = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap_err();
+        assert!(error.to_string().contains("species 5 at (7, 1)"), "{error}");
+        // 3 species get 2 bits, which has room for a 4th
+        snapshot.rule = Rule::default();
+        snapshot.cells = vec![3; 13 * 7];
+        assert!(Snapshot::from_bytes(&snapshot.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let bytes = snapshot().to_bytes();
+        assert!(Snapshot::from_bytes(b"not a snapshot").is_err());
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION as u8 + 1;
+        assert!(Snapshot::from_bytes(&newer).is_err());
+        // the width comes straight after the version
+        let at = MAGIC.len() + 4;
+        for width in [0, u32::MAX] {
+            let mut resized = bytes.clone();
+            resized[at..at + 4].copy_from_slice(&width.to_le_bytes());
+            let error = Snapshot::from_bytes(&resized).unwrap_err();
+            assert!(error.to_string().contains("bad size"), "{error}");
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::{num::NonZeroU64, sync::mpsc, time::Duration};
+
+use anyhow::Context;
+
+use crate::{simulation::WORKGROUP_SIZE, CellLayout, MAX_SPECIES};
+
+/// How many cells of each species there were at some generation, and how it got there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub generation: u64,
+    /// One per species.
+    pub counts: Vec<u64>,
+    /// How many cells are different to the generation before, as counted by the tick to this one.
+    /// `None` if this generation wasn't ticked to (a reset, stepping back, a fresh start...), or
+    /// changes weren't being counted.
+    pub changed: Option<u64>,
+    /// How long the tick to this generation took, averaged over the batch it was run in.
+    /// `None` if this generation wasn't ticked to.
+    pub tick_time: Option<Duration>,
+}
+
+impl Stats {
+    /// Counts on the cpu, `cells` is row-major with one `u32` per cell.
+    /// Leaves `changed` and `tick_time` for whoever did the ticking to fill in.
+    pub fn count(cells: &[u32], species: u32, generation: u64) -> Self {
+        let mut counts = vec![0; species as usize];
+        for &cell in cells {
+            counts[cell as usize] += 1;
+        }
+        Self {
+            generation,
+            counts,
+            changed: None,
+            tick_time: None,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "generation {}:", self.generation)?;
+        let total = self.total().max(1) as f64;
+        for count in &self.counts {
+            write!(f, " {:.1}%", *count as f64 * 100.0 / total)?;
+        }
+        if let Some(changed) = self.changed {
+            write!(f, ", {changed} changed")?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts species on the gpu with the `count` shader, then maps the counts back without blocking.
+/// Only one count is ever on its way back, asking for another before then does nothing.
+pub(crate) struct StatsCounter {
+    pipeline: wgpu::ComputePipeline,
+    /// `bind_groups[i]` counts `data_buffers[i]`.
+    bind_groups: [wgpu::BindGroup; 2],
+    counts_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    species: u32,
+    workgroups: (u32, u32),
+    pending: Option<PendingCount>,
+}
+
+struct PendingCount {
+    generation: u64,
+    tick_time: Option<Duration>,
+    /// Whether the last counter got copied in from the compute kernel's change counts.
+    changed: bool,
+    mapped: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl StatsCounter {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        data_buffers: &[wgpu::Buffer; 2],
+        params_buffer: &wgpu::Buffer,
+        layout: CellLayout,
+        species: u32,
+    ) -> Self {
+        // then one more for how many changed, copied in from the compute kernel's counts
+        let size = (MAX_SPECIES as u64 + 1) * 4;
+        let counts_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Counts Buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Counts Staging Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // its own layout, the compute one already has as many storage buffers as downlevel allows
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Stats Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(NonZeroU64::new(4).unwrap()),
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                        ty: wgpu::BufferBindingType::Uniform,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    count: None,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(size),
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    },
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Stats Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Stats Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: shader,
+            entry_point: "count",
+        });
+        let bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Stats Bind Group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: data_buffers[i].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 8,
+                        resource: counts_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        Self {
+            pipeline,
+            bind_groups,
+            counts_buffer,
+            staging_buffer,
+            species,
+            // one thread per word, like the compute shader
+            workgroups: (
+                layout.row_words().div_ceil(WORKGROUP_SIZE.0),
+                layout.height.div_ceil(WORKGROUP_SIZE.1),
+            ),
+            pending: None,
+        }
+    }
+
+    /// Starts counting `data_buffers[buffer]`, which is at `generation`. `tick_time` is how long
+    /// the tick to it took, if it was ticked to. `changes` is the tracking buffer and which slot of it
+    /// has how many cells that tick changed, if the compute kernel counted them.
+    /// False if the last count hasn't made it back yet.
+    pub fn start(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: usize,
+        generation: u64,
+        tick_time: Option<Duration>,
+        changes: Option<(&wgpu::Buffer, u32)>,
+    ) -> bool {
+        if self.pending.is_some() {
+            return false;
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Stats Encoder"),
+        });
+        encoder.clear_buffer(&self.counts_buffer, 0, None);
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Stats"),
+            timestamp_writes: None,
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &self.bind_groups[buffer], &[]);
+        cpass.dispatch_workgroups(self.workgroups.0, self.workgroups.1, 1);
+        drop(cpass);
+        if let Some((tracking, slot)) = changes {
+            encoder.copy_buffer_to_buffer(tracking, slot as u64 * 4, &self.counts_buffer, MAX_SPECIES as u64 * 4, 4);
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.counts_buffer,
+            0,
+            &self.staging_buffer,
+            0,
+            self.staging_buffer.size(),
+        );
+        queue.submit(Some(encoder.finish()));
+        // has to be after the submit, a buffer can't be used while it's being mapped
+        let (tx, rx) = mpsc::channel();
+        self.staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |res| {
+                let _ = tx.send(res);
+            });
+        self.pending = Some(PendingCount {
+            generation,
+            tick_time,
+            changed: changes.is_some(),
+            mapped: rx,
+        });
+        true
+    }
+
+    /// The last count if it's made it back yet.
+    pub fn try_finish(&mut self, device: &wgpu::Device) -> Option<anyhow::Result<Stats>> {
+        // does nothing on the web, the browser maps things on its own
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = match self.pending.as_ref()?.mapped.try_recv() {
+            Ok(mapped) => Some(mapped),
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => None,
+        };
+        Some(self.finish(mapped))
+    }
+
+    /// Blocks until the last count is back. Native only, like [`crate::capture::PendingFrame::wait`].
+    pub fn wait(&mut self, device: &wgpu::Device) -> Option<anyhow::Result<Stats>> {
+        let pending = self.pending.as_ref()?;
+        device.poll(wgpu::Maintain::Wait);
+        let mapped = pending.mapped.recv().ok();
+        Some(self.finish(mapped))
+    }
+
+    /// `mapped` is what mapping the counts gave back, `None` if it never did.
+    fn finish(
+        &mut self,
+        mapped: Option<Result<(), wgpu::BufferAsyncError>>,
+    ) -> anyhow::Result<Stats> {
+        let pending = self.pending.take().expect("only called with a count pending");
+        mapped
+            .context("Counts were never mapped")?
+            .context("Failed to map counts buffer")?;
+        let data = self.staging_buffer.slice(..).get_mapped_range();
+        let counts: &[u32] = bytemuck::cast_slice(&data);
+        let changed = counts[MAX_SPECIES as usize] as u64;
+        let counts = counts[..self.species as usize]
+            .iter()
+            .map(|&count| count as u64)
+            .collect();
+        drop(data);
+        self.staging_buffer.unmap();
+        Ok(Stats {
+            generation: pending.generation,
+            counts,
+            changed: pending.changed.then_some(changed),
+            tick_time: pending.tick_time,
+        })
+    }
+}
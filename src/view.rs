@@ -0,0 +1,122 @@
+/// How the grid gets fitted onto a window that isn't the same size as it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ScaleMode {
+    /// Biggest size that shows the whole grid, letterboxed.
+    #[default]
+    Fit,
+    /// Smallest size that covers the whole window, cropping the grid.
+    Fill,
+    /// Covers the window exactly, ignoring aspect ratio.
+    Stretch,
+    /// Like fit, but only whole multiples (or fractions) of the grid size so every cell is the same size.
+    Integer,
+}
+
+impl ScaleMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Fit => Self::Fill,
+            Self::Fill => Self::Stretch,
+            Self::Stretch => Self::Integer,
+            Self::Integer => Self::Fit,
+        }
+    }
+
+    /// Works out where the grid should be drawn on a `surface` sized window.
+    pub fn view(self, grid: (u32, u32), surface: (u32, u32)) -> View {
+        let (gw, gh) = (grid.0 as f32, grid.1 as f32);
+        let (sw, sh) = (surface.0 as f32, surface.1 as f32);
+        let (x_scale, y_scale) = match self {
+            Self::Fit => {
+                let s = (sw / gw).min(sh / gh);
+                (s, s)
+            }
+            Self::Fill => {
+                let s = (sw / gw).max(sh / gh);
+                (s, s)
+            }
+            Self::Stretch => (sw / gw, sh / gh),
+            Self::Integer => {
+                let s = (sw / gw).min(sh / gh);
+                // shrinking by a whole number keeps things crisp too, it just skips cells
+                let s = if s >= 1.0 { s.floor() } else { 1.0 / (1.0 / s).ceil() };
+                (s, s)
+            }
+        };
+        let size = [gw * x_scale, gh * y_scale];
+        // round so the grid starts on a pixel boundary, otherwise integer scaling gets blurry edges
+        let offset = [((sw - size[0]) / 2.0).round(), ((sh - size[1]) / 2.0).round()];
+        View {
+            offset,
+            size,
+            age_mode: AgeMode::Off.shader_id(),
+            _pad: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for ScaleMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Fit => "fit",
+            Self::Fill => "fill",
+            Self::Stretch => "stretch",
+            Self::Integer => "integer",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Whether cells get drawn by how long it's been since they changed, on top of (or instead of) their species.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum AgeMode {
+    /// Just the species' colours.
+    #[default]
+    Off,
+    /// The species' colours, dimmer the longer a cell has sat still, so fronts glow.
+    Fade,
+    /// Ignores species, hot for cells that just changed and black for ones that haven't in a while.
+    Heatmap,
+}
+
+impl AgeMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Fade,
+            Self::Fade => Self::Heatmap,
+            Self::Heatmap => Self::Off,
+        }
+    }
+
+    /// Keep in sync with the `AGE_` constants in shader.wgsl.
+    pub fn shader_id(self) -> u32 {
+        match self {
+            Self::Off => 0,
+            Self::Fade => 1,
+            Self::Heatmap => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for AgeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Off => "off",
+            Self::Fade => "fade",
+            Self::Heatmap => "heatmap",
+        };
+        f.write_str(name)
+    }
+}
+
+// keep in sync with View in shader.wgsl
+/// Where the grid is on screen, in pixels, and how it's coloured in.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct View {
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+    /// See [`AgeMode::shader_id`].
+    pub age_mode: u32,
+    pub _pad: u32,
+}